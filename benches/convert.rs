@@ -0,0 +1,75 @@
+//! Conversion of generated documents of a few sizes, to show that conversion time grows
+//! linearly with the size of the document: the throughput should stay the same throughout.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mini_markup::{convert_stream, convert_with_options, Dialect, Direction, Options};
+
+const MEGABYTE: usize = 1024 * 1024;
+
+/// An MXML document of at least `len` bytes, made of nested sections with a bit of everything
+fn generate_mxml(len: usize) -> String {
+    let mut source = String::from("<!DOCTYPE html>\n<html> {\n<body> {\n");
+    let mut section = 0;
+    while source.len() < len {
+        source.push_str(&format!(
+            concat!(
+                "<section id=\"s{0}\"> {{\n",
+                "    <h2> {{Section {0}}}\n",
+                "    <p class=\"intro\"> {{Some <b> {{bold}} text &lbrkt;braced&rbrkt; <br> more}}\n",
+                "    <ul> {{\n        <li> {{one}}\n        <li> {{<a href=\"#s{0}\"> {{two}}}}\n    }}\n",
+                "    <!-- comment {0} -->\n",
+                "    <script> {{if (a < b) {{ run({0}); }}}}\n",
+                "}}\n",
+            ),
+            section
+        ));
+        section += 1;
+    }
+    source.push_str("}\n}\n");
+    source
+}
+
+fn convert(c: &mut Criterion) {
+    let options = Options {
+        dialect: Dialect::html5(),
+        ..Options::default()
+    };
+    let mut group = c.benchmark_group("convert");
+    group.sample_size(10);
+    for megabytes in [1, 2, 4, 8] {
+        let mxml = generate_mxml(megabytes * MEGABYTE);
+        let xml = convert_with_options(&mxml, Direction::MxmlToXml, &options).unwrap();
+        group.throughput(Throughput::Bytes(mxml.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("stream_mxml_to_html", megabytes),
+            &mxml,
+            |b, mxml| {
+                b.iter(|| {
+                    let mut out = Vec::with_capacity(mxml.len() * 2);
+                    convert_stream(mxml.as_bytes(), &mut out, Direction::MxmlToXml, &options)
+                        .unwrap()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("stream_html_to_mxml", megabytes),
+            &xml,
+            |b, xml| {
+                b.iter(|| {
+                    let mut out = Vec::with_capacity(xml.len());
+                    convert_stream(xml.as_bytes(), &mut out, Direction::XmlToMxml, &options)
+                        .unwrap()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("string_mxml_to_html", megabytes),
+            &mxml,
+            |b, mxml| b.iter(|| convert_with_options(mxml, Direction::MxmlToXml, &options)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, convert);
+criterion_main!(benches);
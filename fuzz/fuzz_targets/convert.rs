@@ -0,0 +1,71 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mini_markup::{
+    check, convert_recovering, convert_stream, convert_to_fmt, convert_to_writer,
+    convert_with_options, format_mxml, html_to_mxml, html_to_mxml_str, mxml_to_html,
+    mxml_to_html_str, mxml_to_xml, mxml_to_xml_str, xml_to_mxml, xml_to_mxml_str, Dialects,
+    Direction, Document, Indent, MxmlStyle, Options, Shorthands,
+};
+
+// every public conversion has to return (possibly an error) without panicking, whatever the input
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the options, so that every combination gets a look in
+    let Some((&choice, rest)) = data.split_first() else {
+        return;
+    };
+    let Ok(source) = std::str::from_utf8(rest) else {
+        return;
+    };
+    let _ = mxml_to_xml(source.to_string());
+    let _ = xml_to_mxml(source.to_string());
+    let _ = mxml_to_html(source.to_string());
+    let _ = html_to_mxml(source.to_string());
+    let _ = mxml_to_xml_str(source);
+    let _ = xml_to_mxml_str(source);
+    let _ = mxml_to_html_str(source);
+    let _ = html_to_mxml_str(source);
+
+    let dialects = Dialects::default();
+    let dialect = ["xml", "html5", "xhtml", "svg"][usize::from(choice & 3)];
+    let bit = |n: u8| choice & (1 << n) != 0;
+    let options = Options {
+        dialect: dialects.get(dialect).unwrap().clone(),
+        style: MxmlStyle {
+            brace_on_next_line: bit(2),
+            close_on_own_line: bit(2),
+            indent: Indent::Tabs,
+            annotate_min_lines: bit(3).then_some(1),
+            ..MxmlStyle::default()
+        },
+        keep_brace_whitespace: bit(4),
+        lowercase_tag_names: bit(5),
+        remove_annotations: bit(3),
+        shorthands: Shorthands {
+            selectors: bit(6),
+            one_line: bit(7),
+        },
+    };
+
+    for direction in [Direction::MxmlToXml, Direction::XmlToMxml] {
+        for html in [false, true] {
+            if let Err(errors) = convert_recovering(source, direction, html) {
+                for error in errors {
+                    let _ = error.to_string();
+                }
+            }
+        }
+        let _ = convert_with_options(source, direction, &options);
+        let _ = convert_stream(source.as_bytes(), std::io::sink(), direction, &options);
+        let _ = convert_to_writer(source, std::io::sink(), direction, &options);
+        let _ = convert_to_fmt(source, String::new(), direction, &options);
+        for problem in check(source, direction, &options) {
+            let _ = problem.to_string();
+        }
+    }
+    let _ = format_mxml(source, &options);
+
+    let (document, _) = Document::parse_xml_recovering(source, &options.dialect);
+    let _ = document.to_mxml_styled(&options.style);
+    let (document, _) = Document::parse_mxml_recovering(source, &options.dialect);
+    let _ = document.to_xml();
+});
@@ -1,25 +1,178 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{clap_derive::ArgEnum, Parser};
+use clap::{clap_derive::ArgEnum, Parser, Subcommand};
+use mini_markup::{Dialect, Indent, MxmlStyle, Options, Shorthands};
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about=None)]
+#[clap(author, version, about, long_about=None, args_conflicts_with_subcommands = true)]
 pub struct Args {
-    #[clap(short, long, action)]
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(short, long, action, global = true)]
     /// Print verbose output
     pub verbose: bool,
-    #[clap(short, long, action)]
-    /// Use HTML void-element aware conversion (otherwise HTML conversion may not work correctly)
+    #[clap(short, long, action, global = true)]
+    /// Use HTML void-element aware conversion (otherwise HTML conversion may not work correctly).
+    /// Implied by `.html` and `.htm` paths, or an HTML doctype.
     pub html: bool,
-    #[clap(short, long, arg_enum, value_parser)]
-    /// The target (output) format, defaults to XML
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        global = true,
+        conflicts_with = "html"
+    )]
+    /// The dialect of the markup, which decides its void elements, raw text elements and
+    /// other rules: html5, xhtml, svg, xml, or one loaded with `--dialect-file`.
+    /// By default this is html5 for HTML files, svg and xhtml for `.svg` and `.xhtml` files,
+    /// and xml otherwise.
+    pub dialect: Option<String>,
+    #[clap(long, value_parser, value_name = "FILE", global = true)]
+    /// Load a dialect from a TOML or JSON file, which is used unless `--dialect` picks
+    /// another. It may extend another dialect by name.
+    pub dialect_file: Vec<PathBuf>,
+    #[clap(short, long, arg_enum, value_parser, global = true)]
+    /// The target (output) format. By default this is guessed from the file extensions,
+    /// or else the content of the input file
     pub target: Option<Target>,
+    #[clap(long, value_parser, value_name = "GLOB", global = true)]
+    /// When converting a directory, convert the files matching this glob instead of the
+    /// files with the extension of the source format. Other files are copied.
+    pub include: Vec<String>,
+    #[clap(long, value_parser, value_name = "GLOB", global = true)]
+    /// When converting a directory, leave out the files matching this glob
+    pub exclude: Vec<String>,
+    #[clap(flatten)]
+    pub style: StyleArgs,
+    #[clap(long, action, global = true)]
+    /// When converting to XML, keep the whitespace between start tags and their `{`
+    pub keep_brace_whitespace: bool,
+    #[clap(long, action, global = true)]
+    /// Write tag names in lowercase, like `<div>` for `<DIV>`
+    pub lowercase_tags: bool,
+    #[clap(long, action, global = true)]
+    /// Read selectors like `div.card#main[role=list] {` in MXML as start tags, and write
+    /// elements whose only attributes are `class` and `id` that way
+    pub shorthand_selectors: bool,
+    #[clap(long, action, global = true)]
+    /// Read content on the same line as a start tag, like `<li> "text"` or `<b>: text`, in
+    /// MXML as the element's content, and write elements with short text content that way
+    pub shorthand_one_line: bool,
     #[clap(value_parser)]
-    /// The file path of the file to convert from
-    pub input_file: PathBuf,
+    /// The file path of the file to convert from, or `-` to read from stdin (the default).
+    /// If this is a directory, every file in it is converted into the output directory.
+    pub input_file: Option<PathBuf>,
     #[clap(value_parser)]
-    /// The file path of the output file
-    pub output_file: PathBuf,
+    /// The file path of the output file, or `-` to write to stdout (the default)
+    pub output_file: Option<PathBuf>,
+}
+
+impl Args {
+    /// The conversion options given by the arguments, for a file in `dialect`
+    pub fn options(&self, dialect: Dialect) -> Options {
+        Options {
+            dialect,
+            style: self.style.style(),
+            keep_brace_whitespace: self.keep_brace_whitespace,
+            lowercase_tag_names: self.lowercase_tags,
+            // only comments which could have come from `--annotate` are taken to be annotations
+            remove_annotations: self.style.annotate.is_some(),
+            shorthands: Shorthands {
+                selectors: self.shorthand_selectors,
+                one_line: self.shorthand_one_line,
+            },
+        }
+    }
+
+    /// The input file, or `None` when reading from stdin
+    pub fn input_path(&self) -> Option<&Path> {
+        file_path(&self.input_file)
+    }
+
+    /// The output file, or `None` when writing to stdout
+    pub fn output_path(&self) -> Option<&Path> {
+        file_path(&self.output_file)
+    }
+}
+
+/// `-` stands for stdin or stdout, like it does for most command line tools
+fn file_path(path: &Option<PathBuf>) -> Option<&Path> {
+    path.as_deref().filter(|path| *path != Path::new("-"))
+}
+
+// the layout of MXML output (a doc comment here would replace the program description)
+#[derive(clap::Args)]
+pub struct StyleArgs {
+    #[clap(long, action, global = true)]
+    /// Write `<tag>{` rather than `<tag> {` in MXML output
+    pub no_space_before_brace: bool,
+    #[clap(long, action, global = true)]
+    /// Put `{` on the line after the start tag in MXML output
+    pub brace_on_next_line: bool,
+    #[clap(long, action, global = true)]
+    /// Put the `}` of blocks spanning several lines on its own line in MXML output
+    pub close_on_own_line: bool,
+    #[clap(long, value_parser, value_name = "WIDTH", global = true)]
+    /// Indent lines added to MXML output by this many spaces per level [default: 4]
+    pub indent: Option<usize>,
+    #[clap(long, action, global = true, conflicts_with = "indent")]
+    /// Indent lines added to MXML output with tabs
+    pub tabs: bool,
+    #[clap(long, value_parser, value_name = "LINES", global = true)]
+    /// Annotate the `}` of blocks spanning at least this many lines with the tag name,
+    /// like `} <!-- /section -->`, and remove such annotations when converting to XML
+    pub annotate: Option<usize>,
+}
+
+impl StyleArgs {
+    pub fn style(&self) -> MxmlStyle {
+        let default = MxmlStyle::default();
+        MxmlStyle {
+            space_before_brace: !self.no_space_before_brace,
+            brace_on_next_line: self.brace_on_next_line,
+            close_on_own_line: self.close_on_own_line,
+            indent: match (self.tabs, self.indent) {
+                (true, _) => Indent::Tabs,
+                (false, Some(width)) => Indent::Spaces(width),
+                (false, None) => default.indent,
+            },
+            annotate_min_lines: self.annotate,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Convert a directory like when passing it as the input file, then keep converting
+    /// files whenever they change
+    Watch {
+        #[clap(value_parser)]
+        /// The directory to watch
+        input_dir: PathBuf,
+        #[clap(value_parser)]
+        /// The directory to write converted files to
+        output_dir: PathBuf,
+    },
+    /// Check files for errors, and MXML files for braces which will be kept as text,
+    /// without writing any output. Exits with 1 if there are any errors.
+    Check {
+        #[clap(value_parser, required = true)]
+        /// The files to check, or `-` for stdin
+        files: Vec<PathBuf>,
+        #[clap(short = 'D', long, action)]
+        /// Exit with 1 if there are any warnings too
+        deny_warnings: bool,
+    },
+    /// Format MXML files canonically, in place. Without any files, formats stdin to stdout.
+    Fmt {
+        #[clap(value_parser)]
+        /// The MXML files to format, or `-` for stdin
+        files: Vec<PathBuf>,
+        #[clap(long, action)]
+        /// Don't write anything, just list the files which aren't formatted and exit with 1
+        /// if there are any
+        check: bool,
+    },
 }
 
 #[derive(Clone, Copy, ArgEnum)]
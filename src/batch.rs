@@ -0,0 +1,234 @@
+//! Converting whole directory trees at once
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use mini_markup::{convert_with_options, Dialect, Direction, Options};
+use walkdir::WalkDir;
+
+use crate::args::{Args, Target};
+use crate::detect::{self, Format};
+
+/// Which files in a tree to convert, and how
+pub struct Batch {
+    /// files to convert, or `None` to convert files with the extension of the source format
+    include: Option<GlobSet>,
+    /// files to leave out of the output entirely
+    exclude: GlobSet,
+    target: Target,
+    /// the `--html` flag, the default for every file
+    html: bool,
+    /// the dialect chosen by the `--dialect` flags, for every file
+    dialect: Option<Dialect>,
+    /// the options to convert with, apart from the dialect which depends on the file
+    options: Options,
+    /// every output path written so far, and the file in the input tree written there
+    written: RefCell<HashMap<PathBuf, PathBuf>>,
+}
+
+/// What happened to a single file
+pub enum Outcome {
+    /// converted, and written to the given path
+    Converted(PathBuf),
+    /// not markup, so copied as it is
+    Copied,
+    /// matched an exclude glob, so left out
+    Skipped,
+    /// couldn't be converted, the errors have already been reported
+    Failed,
+}
+
+impl Batch {
+    /// Set up a batch conversion from the command line arguments. Without a `--target`,
+    /// MXML files are converted to XML/HTML.
+    /// # Params
+    /// dialect - the dialect chosen by the `--dialect` flags, if any
+    pub fn new(args: &Args, dialect: Option<Dialect>) -> Result<Self> {
+        let include = if args.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(&args.include)?)
+        };
+        Ok(Batch {
+            include,
+            exclude: glob_set(&args.exclude)?,
+            target: args.target.unwrap_or(Target::Xml),
+            html: args.html,
+            dialect,
+            options: args.options(Dialect::xml()),
+            written: RefCell::default(),
+        })
+    }
+
+    /// Convert every matching file in the `input` tree into the same place in the
+    /// `output` tree, copying everything else, and print a line for each file
+    /// # Returns
+    /// whether every file was converted successfully
+    pub fn run(&self, input: &Path, output: &Path) -> Result<bool> {
+        // don't descend into the output if it's inside the input, or we'd convert our own output
+        let output_dir = fs::create_dir_all(output)
+            .and_then(|_| output.canonicalize())
+            .with_context(|| format!("failed to create `{}`", output.display()))?;
+        self.written.borrow_mut().clear();
+
+        let (mut converted, mut copied, mut failed) = (0, 0, 0);
+        let entries = WalkDir::new(input)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| entry.path().canonicalize().ok().as_ref() != Some(&output_dir));
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    eprintln!("error: failed to read directory: {error}\n");
+                    failed += 1;
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(input)?;
+            let outcome = self.process(input, output, relative)?;
+            report(relative, &outcome);
+            match outcome {
+                Outcome::Converted(_) => converted += 1,
+                Outcome::Copied => copied += 1,
+                Outcome::Skipped => {}
+                Outcome::Failed => failed += 1,
+            }
+        }
+        println!("{converted} converted, {copied} copied, {failed} failed");
+        Ok(failed == 0)
+    }
+
+    /// Convert or copy the file at `relative` in the `input` tree into the `output` tree.
+    /// A file which can't be read, converted or written fails, after printing why, and so
+    /// does one which would overwrite the output of another file, like `p.xml` and `p.html`
+    /// both converting to `p.mxml`.
+    /// # Returns
+    /// what happened to the file
+    pub fn process(&self, input: &Path, output: &Path, relative: &Path) -> Result<Outcome> {
+        if self.exclude.is_match(relative) {
+            return Ok(Outcome::Skipped);
+        }
+        let from = input.join(relative);
+        let mut to = output.join(relative);
+        if let Some(parent) = to.parent() {
+            if let Err(error) = fs::create_dir_all(parent) {
+                return Ok(failed("create", parent, error));
+            }
+        }
+        if !self.matches(relative) {
+            if let Some(outcome) = self.claim(&to, relative) {
+                return Ok(outcome);
+            }
+            return Ok(match fs::copy(&from, &to) {
+                Ok(_) => Outcome::Copied,
+                Err(error) => failed("copy", &from, error),
+            });
+        }
+
+        let source = match fs::read_to_string(&from) {
+            Ok(source) => source,
+            Err(error) => return Ok(failed("read", &from, error)),
+        };
+        let conversion = detect::detect(Some(&from), None, &source, Some(self.target), self.html)?;
+        let extension = match conversion.direction {
+            Direction::XmlToMxml => "mxml",
+            Direction::MxmlToXml if conversion.html => "html",
+            Direction::MxmlToXml => "xml",
+        };
+        let options = Options {
+            dialect: conversion.dialect(self.dialect.as_ref()),
+            ..self.options.clone()
+        };
+        let converted = convert_with_options(&source, conversion.direction, &options);
+        match converted {
+            Ok(converted) => {
+                to.set_extension(extension);
+                if let Some(outcome) = self.claim(&to, relative) {
+                    return Ok(outcome);
+                }
+                if let Err(error) = fs::write(&to, converted) {
+                    return Ok(failed("write", &to, error));
+                }
+                Ok(Outcome::Converted(to.strip_prefix(output)?.to_path_buf()))
+            }
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}\n", error.with_file(&from));
+                }
+                Ok(Outcome::Failed)
+            }
+        }
+    }
+
+    /// Record that the file at `relative` is written to `to`, unless another file has been
+    /// written there already
+    /// # Returns
+    /// `Outcome::Failed` if another file has, after printing which
+    fn claim(&self, to: &Path, relative: &Path) -> Option<Outcome> {
+        let mut written = self.written.borrow_mut();
+        match written.get(to) {
+            Some(other) if other != relative => {
+                eprintln!(
+                    "error: `{}` would overwrite `{}`, which was written from `{}`\n",
+                    relative.display(),
+                    to.display(),
+                    other.display()
+                );
+                Some(Outcome::Failed)
+            }
+            _ => {
+                written.insert(to.to_path_buf(), relative.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// whether the file at `relative` should be converted rather than copied
+    fn matches(&self, relative: &Path) -> bool {
+        match &self.include {
+            Some(include) => include.is_match(relative),
+            None => {
+                let format = Format::from_extension(relative);
+                match self.target {
+                    Target::Xml => format == Some(Format::Mxml),
+                    Target::Mxml => format.is_some_and(|format| format != Format::Mxml),
+                }
+            }
+        }
+    }
+}
+
+/// Print why doing `action` to the file at `path` failed
+fn failed(action: &str, path: &Path, error: io::Error) -> Outcome {
+    eprintln!("error: failed to {action} `{}`: {error}\n", path.display());
+    Outcome::Failed
+}
+
+/// Print a line saying what happened to the file at `relative`
+pub fn report(relative: &Path, outcome: &Outcome) {
+    match outcome {
+        Outcome::Converted(to) => {
+            println!("converted {} -> {}", relative.display(), to.display())
+        }
+        Outcome::Copied => println!("copied    {}", relative.display()),
+        Outcome::Skipped => {}
+        Outcome::Failed => println!("FAILED    {}", relative.display()),
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).with_context(|| format!("invalid glob `{glob}`"))?);
+    }
+    Ok(builder.build()?)
+}
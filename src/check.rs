@@ -0,0 +1,46 @@
+//! Checking files for problems without converting them
+
+use std::path::Path;
+
+use anyhow::Result;
+use mini_markup::{check, Dialect};
+
+use crate::args::Args;
+use crate::{detect, read_input};
+
+/// Check each of `files` (`-` being stdin), printing every problem found
+/// # Returns
+/// whether the files passed: no errors, and no warnings either if `deny_warnings` is set
+pub fn check_files(
+    files: &[impl AsRef<Path>],
+    args: &Args,
+    dialect: Option<&Dialect>,
+    deny_warnings: bool,
+) -> Result<bool> {
+    let (mut errors, mut warnings) = (0, 0);
+    for file in files {
+        let file = file.as_ref();
+        let path = Some(file).filter(|file| *file != Path::new("-"));
+        let source = match read_input(path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("error: {error:#} `{}`\n", file.display());
+                errors += 1;
+                continue;
+            }
+        };
+        let conversion = detect::detect(path, None, &source, args.target, args.html)?;
+        let options = args.options(conversion.dialect(dialect));
+        for problem in check(&source, conversion.direction, &options) {
+            if problem.kind().is_warning() {
+                warnings += 1;
+            } else {
+                errors += 1;
+            }
+            let file = path.unwrap_or_else(|| Path::new("<stdin>"));
+            eprintln!("{}\n", problem.with_file(file));
+        }
+    }
+    eprintln!("{errors} error(s), {warnings} warning(s)");
+    Ok(errors == 0 && (warnings == 0 || !deny_warnings))
+}
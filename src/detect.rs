@@ -0,0 +1,226 @@
+//! Working out which way to convert from file names and content, when not told by flags
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use mini_markup::tokenizer::{Syntax, TokenKind, Tokenizer};
+use mini_markup::{Dialect, Direction};
+
+use crate::args::Target;
+
+/// A file format, as implied by a file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Mxml,
+    Xml,
+    Svg,
+    Xhtml,
+    Html,
+}
+
+impl Format {
+    /// The format implied by the extension of `path`, if it is a known one
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "mxml" => Some(Format::Mxml),
+            "xml" => Some(Format::Xml),
+            "svg" => Some(Format::Svg),
+            "xhtml" => Some(Format::Xhtml),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+}
+
+/// How to convert a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    pub direction: Direction,
+    pub html: bool,
+    /// the format of the XML side of the conversion, if an extension says what it is
+    pub markup: Option<Format>,
+}
+
+impl Conversion {
+    /// The dialect to convert in, which is `chosen` by the `--dialect` flags if they were
+    /// given, then HTML5 in HTML mode, and otherwise the dialect of the file format
+    pub fn dialect(&self, chosen: Option<&Dialect>) -> Dialect {
+        match (chosen, self.markup) {
+            (Some(dialect), _) => dialect.clone(),
+            (None, _) if self.html => Dialect::html5(),
+            (None, Some(Format::Svg)) => Dialect::svg(),
+            (None, Some(Format::Xhtml)) => Dialect::xhtml(),
+            (None, _) => Dialect::xml(),
+        }
+    }
+}
+
+/// Decide how to convert `source`. Explicit flags win, then the extensions of the
+/// input and output paths, and when those don't say, the content of the source.
+/// # Params
+/// input, output - the input and output files, `None` for stdin/stdout
+/// source - the content of the input
+/// target, html - the `--target` and `--html` flags
+/// # Returns
+/// The conversion, or an error if the paths both imply the same format
+pub fn detect(
+    input: Option<&Path>,
+    output: Option<&Path>,
+    source: &str,
+    target: Option<Target>,
+    html: bool,
+) -> Result<Conversion> {
+    let input_format = input.and_then(Format::from_extension);
+    let output_format = output.and_then(Format::from_extension);
+
+    let direction = match (target, input_format, output_format) {
+        (Some(Target::Mxml), _, _) => Direction::XmlToMxml,
+        (Some(Target::Xml), _, _) => Direction::MxmlToXml,
+        (None, Some(from), Some(to)) if (from == Format::Mxml) == (to == Format::Mxml) => {
+            let format = if from == Format::Mxml {
+                "MXML"
+            } else {
+                "XML/HTML"
+            };
+            bail!(
+                "both `{}` and `{}` look like {format} files, pass `--target` to choose which way to convert",
+                input.unwrap_or(Path::new("-")).display(),
+                output.unwrap_or(Path::new("-")).display(),
+            );
+        }
+        (None, Some(Format::Mxml), _)
+        | (None, None, Some(Format::Xml | Format::Svg | Format::Xhtml | Format::Html)) => {
+            Direction::MxmlToXml
+        }
+        (None, Some(_), _) | (None, None, Some(Format::Mxml)) => Direction::XmlToMxml,
+        (None, None, None) if looks_like_mxml(source) => Direction::MxmlToXml,
+        (None, None, None) => Direction::XmlToMxml,
+    };
+
+    let formats = [input_format, output_format];
+    let markup = formats
+        .into_iter()
+        .flatten()
+        .find(|format| *format != Format::Mxml);
+    let html = html
+        || formats.contains(&Some(Format::Html))
+        || (markup.is_none() && looks_like_html(source));
+
+    Ok(Conversion {
+        direction,
+        html,
+        markup,
+    })
+}
+
+/// Whether `source` opens more scopes after start tags than it has end tags. Sources
+/// with neither count as MXML, which is what the converter has always defaulted to.
+fn looks_like_mxml(source: &str) -> bool {
+    let mut end_tags = 0;
+    let mut scopes = 0;
+    let mut after_start_tag = false;
+    for token in Tokenizer::new(source, Syntax::Mxml) {
+        match token.kind {
+            TokenKind::EndTag(_) => end_tags += 1,
+            TokenKind::ScopeOpen if after_start_tag => scopes += 1,
+            TokenKind::Text if token.raw.trim().is_empty() => continue,
+            _ => {}
+        }
+        after_start_tag = matches!(token.kind, TokenKind::StartTag(_));
+    }
+    scopes >= end_tags
+}
+
+/// Whether `source` starts with an HTML doctype or an `<html>` element
+fn looks_like_html(source: &str) -> bool {
+    Tokenizer::new(source, Syntax::Mxml)
+        .find_map(|token| match token.kind {
+            TokenKind::Doctype => Some(
+                token
+                    .raw
+                    .get(..14)
+                    .is_some_and(|start| start.eq_ignore_ascii_case("<!DOCTYPE html")),
+            ),
+            TokenKind::StartTag(tag) | TokenKind::EmptyElementTag(tag) => {
+                Some(tag.name.eq_ignore_ascii_case("html"))
+            }
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_paths(input: &str, output: &str, source: &str) -> Result<Conversion> {
+        detect(
+            Some(Path::new(input)),
+            Some(Path::new(output)),
+            source,
+            None,
+            false,
+        )
+    }
+
+    #[test]
+    fn direction_from_extensions() {
+        let conversion = detect_paths("page.html", "page.mxml", "").unwrap();
+        assert_eq!(conversion.direction, Direction::XmlToMxml);
+        assert!(conversion.html);
+
+        let conversion = detect_paths("page.mxml", "page.SVG", "").unwrap();
+        assert_eq!(conversion.direction, Direction::MxmlToXml);
+        assert!(!conversion.html);
+        assert_eq!(conversion.dialect(None), Dialect::svg());
+        let conversion = detect_paths("page.xhtml", "page.mxml", "").unwrap();
+        assert_eq!(conversion.dialect(None), Dialect::xhtml());
+        // dialect flags still win
+        let html = Dialect::html5();
+        assert_eq!(conversion.dialect(Some(&html)), html);
+
+        // only one known extension is enough
+        let conversion = detect_paths("page.txt", "page.htm", "").unwrap();
+        assert_eq!(conversion.direction, Direction::MxmlToXml);
+        assert!(conversion.html);
+    }
+
+    #[test]
+    fn same_format_is_an_error() {
+        assert!(detect_paths("a.mxml", "b.mxml", "").is_err());
+        assert!(detect_paths("a.html", "b.xml", "").is_err());
+    }
+
+    #[test]
+    fn flags_override() {
+        let conversion = detect(
+            Some(Path::new("a.mxml")),
+            Some(Path::new("b.mxml")),
+            "",
+            Some(Target::Xml),
+            true,
+        )
+        .unwrap();
+        assert_eq!(conversion.direction, Direction::MxmlToXml);
+        assert!(conversion.html);
+    }
+
+    #[test]
+    fn direction_from_content() {
+        let sniff = |source| detect(None, None, source, None, false).unwrap();
+        assert_eq!(sniff("<a> { <b> {x} }").direction, Direction::MxmlToXml);
+        assert_eq!(sniff("<a> <b>{x}</b> </a>").direction, Direction::XmlToMxml);
+        assert_eq!(sniff("plain text").direction, Direction::MxmlToXml);
+    }
+
+    #[test]
+    fn html_from_content() {
+        let sniff = |source| detect(None, None, source, None, false).unwrap().html;
+        assert!(sniff("<!-- hi --><!doctype html><html> {}"));
+        assert!(sniff("<HTML></HTML>"));
+        assert!(!sniff("<?xml version=\"1.0\"?><svg></svg>"));
+        // an extension saying otherwise wins
+        assert!(!detect_paths("a.mxml", "b.xml", "<html> {}").unwrap().html);
+    }
+}
@@ -0,0 +1,364 @@
+//! Dialects of markup, describing the elements which need special treatment
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// see https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+pub const HTML_VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+// see https://html.spec.whatwg.org/multipage/syntax.html#raw-text-elements
+// textarea and title are "escapable" raw text elements which may contain character
+// references, but there's nothing for us to do with those so they're passed through all the same
+pub const HTML_RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "title"];
+// see https://html.spec.whatwg.org/multipage/syntax.html#optional-tags
+// each element whose end tag may be left out, along with the start tags implying its end.
+// html and body only end with their parent or the document, and table sections and rows
+// are also ended by the start of the next section, like the parser does.
+const HTML_OPTIONAL_END_TAGS: [(&str, &[&str]); 18] = [
+    ("html", &[]),
+    ("head", &["body"]),
+    ("body", &[]),
+    ("li", &["li"]),
+    ("dt", &["dt", "dd"]),
+    ("dd", &["dt", "dd"]),
+    (
+        "p",
+        &[
+            "address",
+            "article",
+            "aside",
+            "blockquote",
+            "details",
+            "dialog",
+            "div",
+            "dl",
+            "fieldset",
+            "figcaption",
+            "figure",
+            "footer",
+            "form",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "header",
+            "hgroup",
+            "hr",
+            "main",
+            "menu",
+            "nav",
+            "ol",
+            "p",
+            "pre",
+            "search",
+            "section",
+            "table",
+            "ul",
+        ],
+    ),
+    ("rt", &["rt", "rp"]),
+    ("rp", &["rt", "rp"]),
+    ("optgroup", &["optgroup", "hr"]),
+    ("option", &["option", "optgroup", "hr"]),
+    (
+        "colgroup",
+        &["caption", "colgroup", "thead", "tbody", "tfoot", "tr"],
+    ),
+    ("thead", &["tbody", "tfoot"]),
+    ("tbody", &["tbody", "tfoot"]),
+    ("tfoot", &["tbody"]),
+    ("tr", &["tr", "thead", "tbody", "tfoot"]),
+    ("td", &["td", "th", "tr", "thead", "tbody", "tfoot"]),
+    ("th", &["td", "th", "tr", "thead", "tbody", "tfoot"]),
+];
+// SVG is XML, but CSS and JavaScript are far easier to write without their braces escaped
+const SVG_RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// The rules of a markup language which decide how its documents are parsed: which
+/// elements can't have content, which have content that isn't markup, whether tag
+/// names are case-sensitive, and which end tags may be left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// what the dialect is called, e.g. `html5`
+    pub name: String,
+    /// elements which never have content, so their start tags need no end tag or `/>`
+    pub void_elements: HashSet<String>,
+    /// elements whose content is raw text rather than markup, like `<script>`
+    pub raw_text_elements: HashSet<String>,
+    /// whether tag names are matched ignoring ASCII case, in which case the element
+    /// names in the dialect should be lowercase
+    pub case_insensitive: bool,
+    /// elements whose end tag may be left out, along with the start tags which close them
+    /// when nothing but elements whose end tag may be left out is open inside them, which
+    /// close with them. They are also closed by the end tag of any element containing
+    /// them, and by the end of the document.
+    pub optional_end_tags: HashMap<String, HashSet<String>>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::xml()
+    }
+}
+
+impl Dialect {
+    /// A dialect without any special elements, called `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Dialect {
+            name: name.into(),
+            void_elements: HashSet::new(),
+            raw_text_elements: HashSet::new(),
+            case_insensitive: false,
+            optional_end_tags: HashMap::new(),
+        }
+    }
+
+    /// Plain XML, where every element is closed with an end tag or `/>`
+    pub fn xml() -> Self {
+        Dialect::new("xml")
+    }
+
+    /// HTML5, with its void elements, raw text elements and optional end tags, and whose
+    /// tag names ignore case
+    pub fn html5() -> Self {
+        let optional_end_tags = HTML_OPTIONAL_END_TAGS
+            .iter()
+            .map(|(element, closers)| (element.to_string(), names(closers)))
+            .collect();
+        Dialect {
+            void_elements: names(&HTML_VOID_ELEMENTS),
+            raw_text_elements: names(&HTML_RAW_TEXT_ELEMENTS),
+            case_insensitive: true,
+            optional_end_tags,
+            ..Dialect::new("html5")
+        }
+    }
+
+    /// XHTML, which is HTML written as XML. Its void elements may still be written
+    /// without `/>`, but the content of `<script>` and `<style>` is markup.
+    pub fn xhtml() -> Self {
+        Dialect {
+            void_elements: names(&HTML_VOID_ELEMENTS),
+            ..Dialect::new("xhtml")
+        }
+    }
+
+    /// SVG, which is XML whose `<script>` and `<style>` content is kept as raw text
+    pub fn svg() -> Self {
+        Dialect {
+            raw_text_elements: names(&SVG_RAW_TEXT_ELEMENTS),
+            ..Dialect::new("svg")
+        }
+    }
+
+    /// Whether the element called `name` can't have content
+    pub fn is_void(&self, name: &str) -> bool {
+        self.lookup(&self.void_elements, name)
+    }
+
+    /// Whether the content of the element called `name` is raw text
+    pub fn is_raw_text(&self, name: &str) -> bool {
+        self.lookup(&self.raw_text_elements, name)
+    }
+
+    /// Whether the tag names `a` and `b` name the same element
+    pub fn names_match(&self, a: &str, b: &str) -> bool {
+        a == b || (self.case_insensitive && a.eq_ignore_ascii_case(b))
+    }
+
+    /// Whether the end tag of the element called `name` may be left out
+    pub fn end_tag_optional(&self, name: &str) -> bool {
+        self.closers(name).is_some()
+    }
+
+    /// Whether a start tag called `start_tag` closes the element called `open`, see
+    /// `implied_ends`
+    pub fn closes(&self, open: &str, start_tag: &str) -> bool {
+        self.closers(open)
+            .is_some_and(|closers| self.lookup(closers, start_tag))
+    }
+
+    /// How many of the elements still open a start tag called `start_tag` implies the
+    /// end of, like `<li>` closing an open `<li>` along with a `<p>` inside it. Only
+    /// elements whose end tag is optional are looked through.
+    /// # Params
+    /// open - the names of the elements still open, innermost last
+    pub(crate) fn implied_ends<'a>(
+        &self,
+        open: impl DoubleEndedIterator<Item = &'a str>,
+        start_tag: &str,
+    ) -> usize {
+        let mut ends = 0;
+        for (depth, name) in open.rev().enumerate() {
+            if self.closes(name, start_tag) {
+                ends = depth + 1;
+            }
+            // an element whose end tag is required can't be closed from inside it
+            if !self.end_tag_optional(name) {
+                break;
+            }
+        }
+        ends
+    }
+
+    /// Which of the elements still open an end tag called `end_tag` closes, which is the
+    /// innermost one with a matching name
+    /// # Params
+    /// open - the names of the elements still open, innermost last
+    /// # Returns
+    /// the index of the matching element, if any, and whether it closes cleanly, since
+    /// elements inside it may only be left open if their end tag is optional
+    pub(crate) fn end_tag_match<'a>(
+        &self,
+        open: impl DoubleEndedIterator<Item = &'a str> + ExactSizeIterator,
+        end_tag: &str,
+    ) -> (Option<usize>, bool) {
+        let len = open.len();
+        let mut inside_optional = true;
+        for (depth, name) in open.rev().enumerate() {
+            if self.names_match(name, end_tag) {
+                return (Some(len - 1 - depth), inside_optional);
+            }
+            inside_optional &= self.end_tag_optional(name);
+        }
+        (None, false)
+    }
+
+    /// the start tags closing the element called `name`, if its end tag is optional
+    fn closers(&self, name: &str) -> Option<&HashSet<String>> {
+        self.optional_end_tags.get(name).or_else(|| {
+            self.folds(name)
+                .then(|| self.optional_end_tags.get(&name.to_ascii_lowercase()))
+                .flatten()
+        })
+    }
+
+    /// whether `set` has the element called `name` in it
+    fn lookup(&self, set: &HashSet<String>, name: &str) -> bool {
+        set.contains(name) || (self.folds(name) && set.contains(&name.to_ascii_lowercase()))
+    }
+
+    /// whether `name` might only be found in the dialect once lowercased
+    fn folds(&self, name: &str) -> bool {
+        self.case_insensitive && name.bytes().any(|b| b.is_ascii_uppercase())
+    }
+}
+
+fn names(elements: &[&str]) -> HashSet<String> {
+    elements.iter().map(|name| name.to_string()).collect()
+}
+
+/// Dialects looked up by name, starting out with the built-in `xml`, `html5`,
+/// `xhtml` and `svg` dialects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialects {
+    dialects: BTreeMap<String, Dialect>,
+}
+
+impl Default for Dialects {
+    fn default() -> Self {
+        let mut dialects = Dialects {
+            dialects: BTreeMap::new(),
+        };
+        for dialect in [
+            Dialect::xml(),
+            Dialect::html5(),
+            Dialect::xhtml(),
+            Dialect::svg(),
+        ] {
+            dialects.register(dialect);
+        }
+        dialects
+    }
+}
+
+impl Dialects {
+    /// Add `dialect`, replacing any dialect with the same name
+    pub fn register(&mut self, dialect: Dialect) {
+        self.dialects.insert(dialect.name.clone(), dialect);
+    }
+
+    /// Look up a dialect by name
+    pub fn get(&self, name: &str) -> Option<&Dialect> {
+        self.dialects.get(name)
+    }
+
+    /// The names of every dialect, in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.dialects.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_dialects() {
+        let html = Dialect::html5();
+        assert!(html.is_void("br"));
+        assert!(html.is_raw_text("script"));
+        assert!(!Dialect::xml().is_void("br"));
+        assert!(Dialect::xhtml().is_void("br"));
+        assert!(!Dialect::xhtml().is_raw_text("script"));
+        assert!(Dialect::svg().is_raw_text("style"));
+        // only HTML ignores case
+        assert!(html.is_void("BR"));
+        assert!(!Dialect::xhtml().is_void("BR"));
+        assert!(!Dialect::svg().names_match("foreignObject", "foreignobject"));
+    }
+
+    #[test]
+    fn case_insensitive_lookups() {
+        let mut dialect = Dialect {
+            case_insensitive: false,
+            optional_end_tags: HashMap::from([("li".to_string(), names(&["li"]))]),
+            ..Dialect::html5()
+        };
+        assert!(!dialect.is_void("BR"));
+        assert!(!dialect.names_match("Div", "div"));
+        dialect.case_insensitive = true;
+        assert!(dialect.is_void("BR"));
+        assert!(dialect.is_raw_text("Script"));
+        assert!(dialect.names_match("Div", "div"));
+        assert!(dialect.end_tag_optional("LI"));
+        assert!(dialect.closes("Li", "LI"));
+        assert!(!dialect.closes("li", "ul"));
+    }
+
+    #[test]
+    fn implied_ends_look_through_optional_end_tags() {
+        let html = Dialect::html5();
+        let ends = |open: &[&str], start_tag| html.implied_ends(open.iter().copied(), start_tag);
+        assert_eq!(ends(&["ul", "li", "p"], "li"), 2);
+        assert_eq!(ends(&["dl", "dt", "p"], "dd"), 2);
+        assert_eq!(ends(&["tr", "td", "p"], "td"), 2);
+        assert_eq!(ends(&["table", "tr", "td", "p"], "tr"), 3);
+        assert_eq!(ends(&["li", "p"], "div"), 1);
+        // but not through an element whose end tag is required
+        assert_eq!(ends(&["li", "div", "p"], "li"), 0);
+        assert_eq!(ends(&["li", "ul"], "li"), 0);
+    }
+
+    #[test]
+    fn registering_dialects() {
+        let mut dialects = Dialects::default();
+        assert_eq!(
+            dialects.names().collect::<Vec<_>>(),
+            ["html5", "svg", "xhtml", "xml"]
+        );
+        let custom = Dialect {
+            raw_text_elements: names(&["template"]),
+            ..Dialect::new("vue")
+        };
+        dialects.register(custom.clone());
+        assert_eq!(dialects.get("vue"), Some(&custom));
+
+        // replacing a built-in one
+        dialects.register(Dialect::new("html5"));
+        assert!(!dialects.get("html5").unwrap().is_void("br"));
+    }
+}
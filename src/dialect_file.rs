@@ -0,0 +1,182 @@
+//! Choosing a dialect on the command line, and loading dialects from TOML or JSON files
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use mini_markup::{Dialect, Dialects};
+use serde::Deserialize;
+
+/// A dialect as written in a file. Anything left out is taken from the dialect it
+/// extends, or else from plain XML.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DialectFile {
+    /// defaults to the file name without its extension
+    name: Option<String>,
+    /// the name of a dialect to start from
+    extends: Option<String>,
+    void_elements: Option<HashSet<String>>,
+    raw_text_elements: Option<HashSet<String>>,
+    case_insensitive: Option<bool>,
+    optional_end_tags: Option<HashMap<String, HashSet<String>>>,
+}
+
+/// Load the dialect described by the file at `path`, which is JSON if it has a `.json`
+/// extension and TOML otherwise
+/// # Params
+/// dialects - the dialects it may extend
+pub fn load(path: &Path, dialects: &Dialects) -> Result<Dialect> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read dialect `{}`", path.display()))?;
+    let file: DialectFile = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&text).map_err(anyhow::Error::from)
+    } else {
+        toml::from_str(&text).map_err(anyhow::Error::from)
+    }
+    .with_context(|| format!("invalid dialect `{}`", path.display()))?;
+
+    let base = match &file.extends {
+        Some(name) => find(dialects, name)?.clone(),
+        None => Dialect::xml(),
+    };
+    let name = match file.name {
+        Some(name) => name,
+        None => path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .context("a dialect file without a name needs a file name to name it after")?
+            .to_string(),
+    };
+    let mut dialect = Dialect {
+        name,
+        void_elements: file.void_elements.unwrap_or(base.void_elements),
+        raw_text_elements: file.raw_text_elements.unwrap_or(base.raw_text_elements),
+        case_insensitive: file.case_insensitive.unwrap_or(base.case_insensitive),
+        optional_end_tags: file.optional_end_tags.unwrap_or(base.optional_end_tags),
+    };
+    // tag names are lowercased before being looked up in a dialect ignoring case
+    if dialect.case_insensitive {
+        dialect.void_elements = lowercase(dialect.void_elements);
+        dialect.raw_text_elements = lowercase(dialect.raw_text_elements);
+        dialect.optional_end_tags = dialect
+            .optional_end_tags
+            .into_iter()
+            .map(|(name, closed_by)| (name.to_lowercase(), lowercase(closed_by)))
+            .collect();
+    }
+    Ok(dialect)
+}
+
+fn lowercase(names: HashSet<String>) -> HashSet<String> {
+    names.into_iter().map(|name| name.to_lowercase()).collect()
+}
+
+/// The dialect chosen by the `--dialect` and `--dialect-file` flags, if any. Every
+/// dialect file is registered in turn, so later ones can extend earlier ones.
+/// # Params
+/// name - the `--dialect` flag, defaulting to the last dialect file's dialect
+/// files - the `--dialect-file` flags
+pub fn choose(name: Option<&str>, files: &[impl AsRef<Path>]) -> Result<Option<Dialect>> {
+    let mut dialects = Dialects::default();
+    let mut last = None;
+    for file in files {
+        let dialect = load(file.as_ref(), &dialects)?;
+        last = Some(dialect.name.clone());
+        dialects.register(dialect);
+    }
+    match name.or(last.as_deref()) {
+        Some(name) => Ok(Some(find(&dialects, name)?.clone())),
+        None => Ok(None),
+    }
+}
+
+fn find<'d>(dialects: &'d Dialects, name: &str) -> Result<&'d Dialect> {
+    dialects.get(name).ok_or_else(|| {
+        let names: Vec<&str> = dialects.names().collect();
+        anyhow!(
+            "unknown dialect `{name}`, expected one of: {}",
+            names.join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn loads_toml_and_json() {
+        let dir = std::env::temp_dir().join(format!("dialect_file_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let toml = write(
+            &dir,
+            "vue.toml",
+            "extends = \"html5\"\nraw_text_elements = [\"template\"]\n\n[optional_end_tags]\nli = [\"li\"]\n",
+        );
+        let json = write(
+            &dir,
+            "custom.json",
+            r#"{"name": "mine", "extends": "vue", "case_insensitive": true}"#,
+        );
+
+        let vue = choose(None, &[&toml]).unwrap().unwrap();
+        assert_eq!(vue.name, "vue");
+        assert!(vue.is_void("br"));
+        assert!(vue.is_raw_text("template"));
+        assert!(!vue.is_raw_text("script"));
+        assert!(vue.closes("li", "li"));
+
+        let mine = choose(None, &[&toml, &json]).unwrap().unwrap();
+        assert_eq!(mine.name, "mine");
+        assert!(mine.case_insensitive);
+        assert!(mine.is_raw_text("TEMPLATE"));
+
+        // a built-in one can still be chosen
+        assert_eq!(choose(Some("svg"), &[&toml]).unwrap(), Some(Dialect::svg()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names_lowercased_when_ignoring_case() {
+        let dir = std::env::temp_dir().join(format!("dialect_case_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = write(
+            &dir,
+            "shouty.toml",
+            "case_insensitive = true\nvoid_elements = [\"BR\"]\n\n[optional_end_tags]\nLI = [\"LI\"]\n",
+        );
+        let dialect = choose(None, &[&path]).unwrap().unwrap();
+        assert!(dialect.is_void("br"));
+        assert!(dialect.is_void("Br"));
+        assert!(dialect.closes("li", "LI"));
+
+        let options = mini_markup::Options {
+            dialect,
+            ..mini_markup::Options::default()
+        };
+        let direction = mini_markup::Direction::XmlToMxml;
+        assert_eq!(
+            mini_markup::convert_with_options("<p><br>x</p>", direction, &options).unwrap(),
+            "<p> {<br>x}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_dialects() {
+        let error = choose(Some("nope"), &[] as &[&Path]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown dialect `nope`, expected one of: html5, svg, xhtml, xml"
+        );
+        assert_eq!(choose(None, &[] as &[&Path]).unwrap(), None);
+    }
+}
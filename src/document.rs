@@ -0,0 +1,999 @@
+//! Document tree of parsed markup, which MXML is formatted with
+
+use std::ops::Range;
+
+use crate::dialect::Dialect;
+use crate::error::{Error, ErrorKind, Result};
+use crate::format::MxmlStyle;
+use crate::shorthand::{self, OneLine, Selector, Shorthands};
+use crate::tokenizer::{self, Syntax, Tag, TokenKind, Tokenizer};
+
+/// A parsed XML or MXML document
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Document {
+    pub children: Vec<Node>,
+}
+
+/// A node in the document tree. Apart from elements, every node holds its exact
+/// source text, delimiters included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+    /// the content of a raw text element like `<script>`, kept verbatim in both syntaxes
+    RawText(String),
+    Comment(String),
+    CData(String),
+    ProcessingInstruction(ProcessingInstruction),
+    Doctype(Doctype),
+}
+
+/// An element, made up of its start tag and (possibly) some content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    /// the name of the element
+    pub name: String,
+    /// the attributes of the start tag, in source order
+    pub attributes: Vec<Attribute>,
+    /// the exact source text of the start tag, e.g. `<div class="a">`. Elements written
+    /// as a shorthand in MXML, like `div.a {`, have the start tag it stands for.
+    pub start_tag: String,
+    /// whether the start tag is an empty-element tag, ending in `/>`
+    pub empty_element_tag: bool,
+    /// the content of the element, or `None` if the element has no end tag/scope
+    /// (empty-element tags, void elements, start tags without a scope in MXML)
+    pub children: Option<Vec<Node>>,
+    /// the exact whitespace between the start tag and the `{` of its scope, for elements
+    /// parsed from MXML. It is written back out when rendering MXML.
+    pub brace_whitespace: Option<String>,
+}
+
+/// A processing instruction, like `<?xml-stylesheet href="style.css"?>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessingInstruction {
+    /// the application the instruction is for, e.g. `xml-stylesheet`
+    pub target: String,
+    /// everything after the target and the whitespace following it, e.g. `href="style.css"`
+    pub data: String,
+    /// the exact source text, delimiters included
+    pub raw: String,
+}
+
+impl ProcessingInstruction {
+    fn new(raw: &str) -> Self {
+        let content = raw.strip_prefix("<?").unwrap_or(raw);
+        let content = content.strip_suffix("?>").unwrap_or(content);
+        let (target, data) = content
+            .split_once(char::is_whitespace)
+            .unwrap_or((content, ""));
+        ProcessingInstruction {
+            target: target.to_string(),
+            data: data.trim_start().to_string(),
+            raw: raw.to_string(),
+        }
+    }
+}
+
+/// A document type declaration, like `<!DOCTYPE html>` or
+/// `<!DOCTYPE note [ <!ENTITY x "y"> ]>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Doctype {
+    /// the name of the root element, e.g. `html`
+    pub name: String,
+    /// the content between the brackets of the internal subset, if there is one
+    pub internal_subset: Option<String>,
+    /// the exact source text, delimiters included
+    pub raw: String,
+}
+
+impl Doctype {
+    fn new(raw: &str) -> Self {
+        let content = raw.get(2..).unwrap_or_default();
+        let content = content.strip_suffix('>').unwrap_or(content);
+        // skip the `DOCTYPE` keyword itself
+        let after_keyword = content.trim_start_matches(|c: char| !c.is_whitespace() && c != '[');
+        let after_keyword = after_keyword.trim_start();
+        let name_len = after_keyword
+            .find(|c: char| c.is_whitespace() || c == '[')
+            .unwrap_or(after_keyword.len());
+        let internal_subset = content.find('[').and_then(|start| {
+            let end = content.rfind(']').filter(|&end| end > start)?;
+            Some(content[start + 1..end].to_string())
+        });
+        Doctype {
+            name: after_keyword[..name_len].to_string(),
+            internal_subset,
+            raw: raw.to_string(),
+        }
+    }
+}
+
+/// An attribute of an element, e.g. `class="a"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    /// the value without its surrounding quotes, or `None` for attributes
+    /// without a value, like `disabled` in `<input disabled>`
+    pub value: Option<String>,
+}
+
+impl From<&tokenizer::Attribute<'_>> for Attribute {
+    fn from(attribute: &tokenizer::Attribute) -> Self {
+        Attribute {
+            name: attribute.name.to_string(),
+            value: attribute.value.map(str::to_string),
+        }
+    }
+}
+
+impl Element {
+    fn new(tag: &Tag, start_tag: &str, empty_element_tag: bool) -> Self {
+        Element {
+            name: tag.name.to_string(),
+            attributes: tag.attributes.iter().map(Attribute::from).collect(),
+            start_tag: start_tag.to_string(),
+            empty_element_tag,
+            children: None,
+            brace_whitespace: None,
+        }
+    }
+
+    /// Look up the value of an attribute by name
+    /// # Returns
+    /// `None` if there is no such attribute, `Some(None)` if it has no value
+    pub fn attribute(&self, name: &str) -> Option<Option<&str>> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| attribute.value.as_deref())
+    }
+}
+
+/// Lowercase the tag names of every element in `nodes` and their descendants
+fn lowercase_tag_names(nodes: &mut [Node]) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            element.start_tag = lowercase_tag_name(&element.start_tag, &element.name);
+            element.name.make_ascii_lowercase();
+            if let Some(children) = &mut element.children {
+                lowercase_tag_names(children);
+            }
+        }
+    }
+}
+
+/// `start_tag` with its tag name, `name`, lowercased
+pub(crate) fn lowercase_tag_name(start_tag: &str, name: &str) -> String {
+    let rest = start_tag.get(1 + name.len()..).unwrap_or_default();
+    format!("<{}{rest}", name.to_ascii_lowercase())
+}
+
+/// A scope which has been opened but not yet closed while parsing MXML, along
+/// with the span of its `{`
+enum Scope {
+    /// scope belonging to an element, collecting the element's children
+    Element(Element, Vec<Node>, Range<usize>),
+    /// scope without a tag before it, whose braces are kept as literal text
+    Literal(Range<usize>),
+}
+
+/// How deeply elements (or scopes) may be nested before parsing gives up, so that
+/// pathological input can't overflow the stack when the tree is rendered or dropped
+pub const MAX_NESTING_DEPTH: usize = 1024;
+
+/// An element whose end tag hasn't been found yet while parsing XML
+struct OpenElement {
+    element: Element,
+    children: Vec<Node>,
+    /// span of the start tag
+    span: Range<usize>,
+}
+
+impl Document {
+    /// Parse XML, where elements are delimited by start and end tags
+    /// # Params
+    /// source - the XML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// # Returns
+    /// The document, or the first error in the source
+    pub fn parse_xml(source: &str, dialect: &Dialect) -> Result<Document> {
+        first_error(Document::parse_xml_recovering(source, dialect))
+    }
+
+    /// Parse XML like `parse_xml`, but carry on past errors to find every problem in the source.
+    /// Unexpected end tags are kept as text, and unclosed elements are closed where the
+    /// enclosing element (or the document) ends. Parsing stops at elements nested more
+    /// than `MAX_NESTING_DEPTH` deep.
+    ///
+    /// Elements whose end tag the dialect lets be left out are closed where the dialect
+    /// implies, without an error, and the whitespace their content ends with goes after them.
+    /// # Params
+    /// source - the XML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// # Returns
+    /// The best-effort document, along with every error in the order they were found
+    pub fn parse_xml_recovering(source: &str, dialect: &Dialect) -> (Document, Vec<Error>) {
+        let mut children = Vec::new();
+        let mut open: Vec<OpenElement> = Vec::new();
+        let mut errors = Vec::new();
+        let mut too_deep = false;
+
+        let tokens = Tokenizer::new(source, Syntax::Xml).with_dialect(dialect);
+        for token in tokens {
+            if let TokenKind::StartTag(tag) | TokenKind::EmptyElementTag(tag) = &token.kind {
+                let names = open.iter().map(|o| o.element.name.as_str());
+                for _ in 0..dialect.implied_ends(names, tag.name) {
+                    close_innermost(&mut open, &mut children, true);
+                }
+            }
+            let node = match token.kind {
+                TokenKind::StartTag(tag) if !dialect.is_void(tag.name) => {
+                    if open.len() == MAX_NESTING_DEPTH {
+                        errors.push(Error::new(ErrorKind::NestingTooDeep, source, token.span));
+                        too_deep = true;
+                        break;
+                    }
+                    open.push(OpenElement {
+                        element: Element::new(&tag, token.raw, false),
+                        children: Vec::new(),
+                        span: token.span,
+                    });
+                    continue;
+                }
+                TokenKind::StartTag(tag) => Node::Element(Element::new(&tag, token.raw, false)),
+                TokenKind::EmptyElementTag(tag) => {
+                    Node::Element(Element::new(&tag, token.raw, true))
+                }
+                TokenKind::EndTag(tag) => {
+                    let names = open.iter().map(|o| o.element.name.as_str());
+                    let (matching, closes_cleanly) = dialect.end_tag_match(names, tag.name);
+                    if !closes_cleanly {
+                        let kind = match open.last() {
+                            Some(innermost) => ErrorKind::MismatchedEndTag {
+                                expected: innermost.element.name.clone(),
+                                found: tag.name.to_string(),
+                            },
+                            None => ErrorKind::UnmatchedEndTag(tag.name.to_string()),
+                        };
+                        errors.push(Error::new(kind, source, token.span.clone()));
+                    }
+                    match matching {
+                        Some(index) => {
+                            // anything still open inside the matched element closes with it
+                            while open.len() > index {
+                                let implied = open.len() > index + 1
+                                    && innermost_end_tag_optional(&open, dialect);
+                                close_innermost(&mut open, &mut children, implied);
+                            }
+                            continue;
+                        }
+                        None => Node::Text(token.raw.to_string()),
+                    }
+                }
+                kind => leaf_node(kind, token.raw),
+            };
+            match open.last_mut() {
+                Some(innermost) => innermost.children.push(node),
+                None => children.push(node),
+            }
+        }
+        if !too_deep {
+            let unclosed = open
+                .iter()
+                .filter(|unclosed| !dialect.end_tag_optional(&unclosed.element.name));
+            for unclosed in unclosed {
+                let kind = ErrorKind::UnclosedStartTag(unclosed.element.name.clone());
+                errors.push(Error::new(kind, source, unclosed.span.clone()));
+            }
+        }
+        while !open.is_empty() {
+            let implied = innermost_end_tag_optional(&open, dialect);
+            close_innermost(&mut open, &mut children, implied);
+        }
+
+        (Document { children }, errors)
+    }
+
+    /// Parse MXML, where elements are delimited by a start tag followed by a
+    /// curly brace scope. Braces without a tag before them are kept as text.
+    /// # Params
+    /// source - the MXML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// # Returns
+    /// The document, or the first error in the source
+    pub fn parse_mxml(source: &str, dialect: &Dialect) -> Result<Document> {
+        first_error(Document::parse_mxml_recovering(source, dialect))
+    }
+
+    /// Parse MXML like `parse_mxml`, but carry on past errors to find every problem in the source.
+    /// Unmatched `}` are kept as text, and unclosed scopes are closed at the end of the document.
+    /// Parsing stops at scopes nested more than `MAX_NESTING_DEPTH` deep.
+    ///
+    /// Raw text elements may either have a scope, in which case their content runs up to the
+    /// balancing `}`, or be closed by an end tag like in XML.
+    /// # Params
+    /// source - the MXML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// # Returns
+    /// The best-effort document, along with every error in the order they were found
+    pub fn parse_mxml_recovering(source: &str, dialect: &Dialect) -> (Document, Vec<Error>) {
+        let (document, errors, _) = Document::parse_mxml_with_warnings(source, dialect);
+        (document, errors)
+    }
+
+    /// Parse MXML like `parse_mxml_recovering`, also collecting warnings about braces which
+    /// are kept as literal text rather than opening an element's scope
+    /// # Params
+    /// source - the MXML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// # Returns
+    /// The best-effort document, every error, and every warning, each in the order they were found
+    pub fn parse_mxml_with_warnings(
+        source: &str,
+        dialect: &Dialect,
+    ) -> (Document, Vec<Error>, Vec<Error>) {
+        Document::parse_mxml_with_shorthands(source, dialect, &Shorthands::default())
+    }
+
+    /// Parse MXML like `parse_mxml_with_warnings`, also recognising the shorthand forms
+    /// in `shorthands`. Elements written as shorthands get the start tag they stand for.
+    /// # Params
+    /// source - the MXML text
+    /// dialect - which elements are void or raw text elements, and how tag names match
+    /// shorthands - which shorthand forms to recognise
+    /// # Returns
+    /// The best-effort document, every error, and every warning, each in the order they were found
+    pub fn parse_mxml_with_shorthands(
+        source: &str,
+        dialect: &Dialect,
+        shorthands: &Shorthands,
+    ) -> (Document, Vec<Error>, Vec<Error>) {
+        let mut children = Vec::new();
+        let mut scopes: Vec<Scope> = Vec::new();
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        let mut too_deep = false;
+        // an element with one-line content like `<b>: text` whose line hasn't ended yet
+        let mut unfinished_line = None;
+
+        let tokens = Tokenizer::new(source, Syntax::Mxml).with_dialect(dialect);
+        for token in tokens {
+            if let Some(name) = unfinished_line.take() {
+                let kind = ErrorKind::OneLineInterrupted(name);
+                errors.push(Error::new(kind, source, token.span.clone()));
+            }
+            let node = match token.kind {
+                TokenKind::ScopeOpen => {
+                    if scopes.len() == MAX_NESTING_DEPTH {
+                        errors.push(Error::new(ErrorKind::NestingTooDeep, source, token.span));
+                        too_deep = true;
+                        break;
+                    }
+                    let container = current_children(&mut children, &mut scopes);
+                    let element = take_scoped_element(container, dialect).or_else(|| {
+                        shorthands
+                            .selectors
+                            .then(|| take_selector_element(container, dialect))
+                            .flatten()
+                    });
+                    let scope = match element {
+                        Some(element) => Scope::Element(element, Vec::new(), token.span),
+                        None => {
+                            let kind = literal_scope_warning(container, dialect);
+                            warnings.push(Error::new(kind, source, token.span.clone()));
+                            container.push(Node::Text(token.raw.to_string()));
+                            Scope::Literal(token.span)
+                        }
+                    };
+                    scopes.push(scope);
+                    continue;
+                }
+                TokenKind::ScopeClose => match scopes.pop() {
+                    Some(scope) => match close_scope(scope) {
+                        Some(node) => node,
+                        None => Node::Text(token.raw.to_string()),
+                    },
+                    None => {
+                        let kind = ErrorKind::UnmatchedScopeClose;
+                        errors.push(Error::new(kind, source, token.span));
+                        Node::Text(token.raw.to_string())
+                    }
+                },
+                TokenKind::StartTag(tag) => Node::Element(Element::new(&tag, token.raw, false)),
+                TokenKind::EmptyElementTag(tag) => {
+                    Node::Element(Element::new(&tag, token.raw, true))
+                }
+                TokenKind::Text if shorthands.one_line => {
+                    let container = current_children(&mut children, &mut scopes);
+                    let Some(element) = one_line_element(container, dialect) else {
+                        container.push(Node::Text(token.raw.to_string()));
+                        continue;
+                    };
+                    let Some(line) = shorthand::one_line(token.raw) else {
+                        if shorthand::quoted_mid_line(token.raw) {
+                            let kind = ErrorKind::QuotedTextKept(element.name.clone());
+                            warnings.push(Error::new(kind, source, token.span.clone()));
+                        }
+                        container.push(Node::Text(token.raw.to_string()));
+                        continue;
+                    };
+                    fill_one_line_element(element, &line);
+                    if !line.ends_line {
+                        unfinished_line = Some(element.name.clone());
+                    }
+                    if line.rest.is_empty() {
+                        continue;
+                    }
+                    Node::Text(line.rest.to_string())
+                }
+                TokenKind::EndTag(tag) => {
+                    let container = current_children(&mut children, &mut scopes);
+                    match take_raw_text_element(container, tag.name, dialect) {
+                        Some(element) => Node::Element(element),
+                        // other end tags have no meaning in MXML, so leave them be
+                        None => Node::Text(token.raw.to_string()),
+                    }
+                }
+                kind => leaf_node(kind, token.raw),
+            };
+            current_children(&mut children, &mut scopes).push(node);
+        }
+        if !too_deep {
+            for unclosed in &scopes {
+                let (Scope::Element(_, _, span) | Scope::Literal(span)) = unclosed;
+                errors.push(Error::new(ErrorKind::UnclosedScope, source, span.clone()));
+            }
+        }
+        while let Some(scope) = scopes.pop() {
+            if let Some(node) = close_scope(scope) {
+                current_children(&mut children, &mut scopes).push(node);
+            }
+        }
+
+        (Document { children }, errors, warnings)
+    }
+
+    /// Lowercase the tag names of every element, in their start tags too, so that it's
+    /// how they are written out
+    pub fn lowercase_tag_names(&mut self) {
+        lowercase_tag_names(&mut self.children);
+    }
+
+    /// Render the document as XML, closing elements with end tags
+    pub fn to_xml(&self) -> String {
+        self.render(Syntax::Xml, &MxmlStyle::default(), false)
+    }
+
+    /// Render the document as XML like `to_xml`, but keep the whitespace which was between
+    /// start tags and their `{` in MXML, so that only the braces themselves are replaced
+    pub fn to_xml_keeping_brace_whitespace(&self) -> String {
+        self.render(Syntax::Xml, &MxmlStyle::default(), true)
+    }
+
+    /// Render the document as MXML, closing elements with curly brace scopes
+    pub fn to_mxml(&self) -> String {
+        self.to_mxml_styled(&MxmlStyle::default())
+    }
+
+    /// Render the document as MXML, laying out the scopes according to `style`. Scopes
+    /// parsed from MXML keep the whitespace they had before their `{` instead.
+    pub fn to_mxml_styled(&self, style: &MxmlStyle) -> String {
+        self.render(Syntax::Mxml, style, false)
+    }
+
+    fn render(&self, syntax: Syntax, style: &MxmlStyle, keep_brace_whitespace: bool) -> String {
+        let mut out = String::new();
+        let render = Render {
+            syntax,
+            style,
+            keep_brace_whitespace,
+        };
+        write_nodes(&self.children, &mut out, &render, 0);
+        out
+    }
+}
+
+/// turn the result of a recovering parse into a strict one
+fn first_error((document, errors): (Document, Vec<Error>)) -> Result<Document> {
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(document),
+    }
+}
+
+/// close the innermost open element while parsing XML, adding it to its parent
+/// # Params
+/// implied - whether the element's end tag was left out, in which case the whitespace
+/// its content ends with goes after it instead, so that its scope ends with the content
+fn close_innermost(open: &mut Vec<OpenElement>, children: &mut Vec<Node>, implied: bool) {
+    if let Some(OpenElement {
+        mut element,
+        children: mut element_children,
+        ..
+    }) = open.pop()
+    {
+        let trailing = match element_children.last_mut() {
+            Some(Node::Text(text)) if implied => {
+                let trailing = text.split_off(trim_whitespace_end(text).len());
+                if text.is_empty() {
+                    element_children.pop();
+                }
+                (!trailing.is_empty()).then_some(Node::Text(trailing))
+            }
+            _ => None,
+        };
+        element.children = Some(element_children);
+        let parent = match open.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => children,
+        };
+        parent.push(Node::Element(element));
+        parent.extend(trailing);
+    }
+}
+
+/// whether the innermost open element may be closed without an end tag
+fn innermost_end_tag_optional(open: &[OpenElement], dialect: &Dialect) -> bool {
+    open.last()
+        .is_some_and(|innermost| dialect.end_tag_optional(&innermost.element.name))
+}
+
+/// `text` without the HTML whitespace it ends with
+pub(crate) fn trim_whitespace_end(text: &str) -> &str {
+    text.trim_end_matches(|c: char| c.is_ascii_whitespace())
+}
+
+/// close a scope while parsing MXML
+/// # Returns
+/// the finished element for element scopes, `None` for literal scopes
+fn close_scope(scope: Scope) -> Option<Node> {
+    match scope {
+        Scope::Element(mut element, element_children, _) => {
+            element.children = Some(element_children);
+            Some(Node::Element(element))
+        }
+        Scope::Literal(_) => None,
+    }
+}
+
+/// make the node for a token which can't contain other nodes
+fn leaf_node(kind: TokenKind, raw: &str) -> Node {
+    let raw = raw.to_string();
+    match kind {
+        TokenKind::Comment => Node::Comment(raw),
+        TokenKind::CData => Node::CData(raw),
+        TokenKind::ProcessingInstruction => {
+            Node::ProcessingInstruction(ProcessingInstruction::new(&raw))
+        }
+        TokenKind::Doctype => Node::Doctype(Doctype::new(&raw)),
+        TokenKind::RawText => Node::RawText(raw),
+        _ => Node::Text(raw),
+    }
+}
+
+/// the list of nodes that new nodes should currently be added to while parsing MXML
+fn current_children<'s>(children: &'s mut Vec<Node>, scopes: &'s mut [Scope]) -> &'s mut Vec<Node> {
+    scopes
+        .iter_mut()
+        .rev()
+        .find_map(|scope| match scope {
+            Scope::Element(_, element_children, _) => Some(element_children),
+            Scope::Literal(_) => None,
+        })
+        .unwrap_or(children)
+}
+
+/// If the scope being opened belongs to the start tag just before it (with only
+/// whitespace in between), remove that element from `container` and return it.
+/// The whitespace between the start tag and the scope is moved into the element.
+fn take_scoped_element(container: &mut Vec<Node>, dialect: &Dialect) -> Option<Element> {
+    let has_gap = matches!(container.last(), Some(Node::Text(t)) if t.trim().is_empty());
+    let element_index = container.len().checked_sub(1 + has_gap as usize)?;
+    match &container[element_index] {
+        Node::Element(element)
+            if element.children.is_none()
+                && !element.empty_element_tag
+                && !dialect.is_void(&element.name) => {}
+        _ => return None,
+    }
+    let gap = match has_gap.then(|| container.pop()).flatten() {
+        Some(Node::Text(gap)) => gap,
+        _ => String::new(),
+    };
+    match container.pop() {
+        Some(Node::Element(mut element)) => {
+            element.brace_whitespace = Some(gap);
+            Some(element)
+        }
+        _ => None,
+    }
+}
+
+/// If the scope being opened comes after a selector standing for a start tag, like
+/// `div.card {`, take the selector (and the whitespace after it) out of the text at the
+/// end of `container`, and return the element it stands for
+fn take_selector_element(container: &mut Vec<Node>, dialect: &Dialect) -> Option<Element> {
+    let Some(Node::Text(text)) = container.last_mut() else {
+        return None;
+    };
+    let range = shorthand::find_selector(text, dialect)?;
+    let selector = Selector::parse(&text[range.clone()])?;
+    let mut element = selector.element();
+    element.brace_whitespace = Some(text[range.end..].to_string());
+    text.truncate(range.start);
+    if text.is_empty() {
+        container.pop();
+    }
+    Some(element)
+}
+
+/// The element which text coming next in `container` would be the one-line content of,
+/// like `<li> "text"`, if any
+pub(crate) fn one_line_element<'c>(
+    container: &'c mut [Node],
+    dialect: &Dialect,
+) -> Option<&'c mut Element> {
+    let Some(Node::Element(element)) = container.last_mut() else {
+        return None;
+    };
+    let fillable =
+        element.children.is_none() && !element.empty_element_tag && !dialect.is_void(&element.name);
+    fillable.then_some(element)
+}
+
+/// Make one-line content the content of `element`
+fn fill_one_line_element(element: &mut Element, line: &OneLine) {
+    let content = Node::Text(line.content.to_string());
+    element.children = Some(if line.content.is_empty() {
+        Vec::new()
+    } else {
+        vec![content]
+    });
+    element.brace_whitespace = Some(line.gap.to_string());
+}
+
+/// Why a scope being opened in `container` is a literal one
+fn literal_scope_warning(container: &[Node], dialect: &Dialect) -> ErrorKind {
+    let has_gap = matches!(container.last(), Some(Node::Text(t)) if t.trim().is_empty());
+    let before = container.len().checked_sub(1 + has_gap as usize);
+    match before.map(|index| &container[index]) {
+        Some(Node::Element(element))
+            if element.children.is_none()
+                && (element.empty_element_tag || dialect.is_void(&element.name)) =>
+        {
+            ErrorKind::ScopeOnVoidElement(element.name.clone())
+        }
+        _ => ErrorKind::LiteralScope,
+    }
+}
+
+/// If the end tag `name` closes a raw text element written without a scope in MXML, like
+/// `<script>...</script>`, remove that element and its content from `container` and
+/// return the finished element.
+fn take_raw_text_element(
+    container: &mut Vec<Node>,
+    name: &str,
+    dialect: &Dialect,
+) -> Option<Element> {
+    let has_content = matches!(container.last(), Some(Node::RawText(_)));
+    let element_index = container.len().checked_sub(1 + has_content as usize)?;
+    match &container[element_index] {
+        Node::Element(element)
+            if dialect.names_match(&element.name, name)
+                && element.children.is_none()
+                && !element.empty_element_tag
+                && dialect.is_raw_text(name) => {}
+        _ => return None,
+    }
+    let content: Vec<Node> = container.drain(element_index + 1..).collect();
+    match container.pop() {
+        Some(Node::Element(mut element)) => {
+            element.children = Some(content);
+            Some(element)
+        }
+        _ => None,
+    }
+}
+
+/// whether every `}` in `s` closes a `{` before it, and every `{` is closed
+pub(crate) fn braces_balance(s: &str) -> bool {
+    let mut depth: usize = 0;
+    for c in s.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// How to render nodes
+pub(crate) struct Render<'a> {
+    pub syntax: Syntax,
+    /// the layout of the scopes, for MXML
+    pub style: &'a MxmlStyle,
+    /// for XML, whether to keep the whitespace which was between start tags and their `{`
+    pub keep_brace_whitespace: bool,
+}
+
+/// Render `nodes`, nested `depth` elements deep
+pub(crate) fn write_nodes(nodes: &[Node], out: &mut String, render: &Render, depth: usize) {
+    for node in nodes {
+        match node {
+            Node::Element(element) => {
+                out.push_str(&element.start_tag);
+                if let Some(children) = &element.children {
+                    // raw text can only go in a scope if its braces don't end it early
+                    let scope_fits = children.iter().all(|child| match child {
+                        Node::RawText(raw) => braces_balance(raw),
+                        _ => true,
+                    });
+                    if render.syntax == Syntax::Mxml && scope_fits {
+                        write_scope(element, children, out, render, depth);
+                    } else {
+                        if render.keep_brace_whitespace {
+                            out.push_str(element.brace_whitespace.as_deref().unwrap_or_default());
+                        }
+                        write_nodes(children, out, render, depth + 1);
+                        out.push_str("</");
+                        out.push_str(&element.name);
+                        out.push('>');
+                    }
+                }
+            }
+            Node::Text(raw) | Node::RawText(raw) | Node::Comment(raw) | Node::CData(raw) => {
+                out.push_str(raw)
+            }
+            Node::ProcessingInstruction(instruction) => out.push_str(&instruction.raw),
+            Node::Doctype(doctype) => out.push_str(&doctype.raw),
+        }
+    }
+}
+
+/// Render the children of `element` in a curly brace scope, laid out according to the style
+fn write_scope(
+    element: &Element,
+    children: &[Node],
+    out: &mut String,
+    render: &Render,
+    depth: usize,
+) {
+    let style = render.style;
+    match &element.brace_whitespace {
+        Some(whitespace) => {
+            out.push_str(whitespace);
+            out.push('{');
+        }
+        None => style.write_open(out, depth),
+    }
+    let content_start = out.len();
+    write_nodes(children, out, render, depth + 1);
+    let content = &out[content_start..];
+    let lines = content.matches('\n').count();
+    let last_line = content.rsplit('\n').next().unwrap_or_default();
+    if style.close_on_own_line && lines > 0 && !last_line.trim().is_empty() {
+        out.push('\n');
+        style.indent.write(out, depth);
+    }
+    style.write_close(out, &element.name, lines);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_xml_nested() {
+        let document = Document::parse_xml("<a><b/>text</a>", &Dialect::xml()).unwrap();
+        let b = Element {
+            name: "b".into(),
+            attributes: vec![],
+            start_tag: "<b/>".into(),
+            empty_element_tag: true,
+            children: None,
+            brace_whitespace: None,
+        };
+        let a = Element {
+            name: "a".into(),
+            attributes: vec![],
+            start_tag: "<a>".into(),
+            empty_element_tag: false,
+            children: Some(vec![Node::Element(b), Node::Text("text".into())]),
+            brace_whitespace: None,
+        };
+        assert_eq!(document.children, vec![Node::Element(a)]);
+    }
+
+    #[test]
+    fn element_attributes() {
+        let document =
+            Document::parse_xml("<a href=\"/x/\" title='1 > 0' hidden/>", &Dialect::xml()).unwrap();
+        let Some(Node::Element(a)) = document.children.first() else {
+            panic!("expected an element, got {:?}", document.children);
+        };
+        assert!(a.empty_element_tag);
+        assert_eq!(a.attribute("href"), Some(Some("/x/")));
+        assert_eq!(a.attribute("title"), Some(Some("1 > 0")));
+        assert_eq!(a.attribute("hidden"), Some(None));
+        assert_eq!(a.attribute("id"), None);
+    }
+
+    #[test]
+    fn parse_mxml_matches_parse_xml() {
+        let html = Dialect::html5();
+        let xml = Document::parse_xml("<a><br> <b>x</b></a>", &html).unwrap();
+        let mut mxml = Document::parse_mxml("<a> {<br> <b> {x}}", &html).unwrap();
+        // apart from the whitespace before the braces, which only MXML has
+        crate::format::forget_brace_whitespace(&mut mxml.children);
+        assert_eq!(xml, mxml);
+    }
+
+    #[test]
+    fn literal_scopes_are_text() {
+        let document = Document::parse_mxml("<a> {x {y}}", &Dialect::xml()).unwrap();
+        assert_eq!(document.to_xml(), "<a>x {y}</a>");
+    }
+
+    #[test]
+    fn mismatched_end_tag_error() {
+        let error = Document::parse_xml("<a>\n  </b>", &Dialect::xml()).unwrap_err();
+        let kind = ErrorKind::MismatchedEndTag {
+            expected: "a".into(),
+            found: "b".into(),
+        };
+        assert_eq!(error.kind(), &kind);
+        assert_eq!((error.line(), error.column(), error.span()), (2, 3, 6..10));
+    }
+
+    #[test]
+    fn recovering_xml_reports_every_error() {
+        let source = "<a><b></a>\n</c>\n<d>";
+        let (document, errors) = Document::parse_xml_recovering(source, &Dialect::xml());
+        let kinds: Vec<&ErrorKind> = errors.iter().map(Error::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &ErrorKind::MismatchedEndTag {
+                    expected: "b".into(),
+                    found: "a".into()
+                },
+                &ErrorKind::UnmatchedEndTag("c".into()),
+                &ErrorKind::UnclosedStartTag("d".into()),
+            ]
+        );
+        assert_eq!(document.to_mxml(), "<a> {<b> {}}\n</c>\n<d> {}");
+    }
+
+    #[test]
+    fn recovering_mxml_reports_every_error() {
+        let source = "<a> {}}\n<b> {\n<c> {";
+        let (document, errors) = Document::parse_mxml_recovering(source, &Dialect::xml());
+        let lines: Vec<(&ErrorKind, usize)> = errors.iter().map(|e| (e.kind(), e.line())).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (&ErrorKind::UnmatchedScopeClose, 1),
+                (&ErrorKind::UnclosedScope, 2),
+                (&ErrorKind::UnclosedScope, 3),
+            ]
+        );
+        assert_eq!(document.to_xml(), "<a></a>}\n<b>\n<c></c></b>");
+    }
+
+    #[test]
+    fn processing_instructions() {
+        let source = "<?xml version=\"1.0\"?>\n<?xml-stylesheet  href=\"a.xsl\"?><?empty?>";
+        let document = Document::parse_xml(source, &Dialect::xml()).unwrap();
+        let instructions: Vec<(&str, &str)> = document
+            .children
+            .iter()
+            .filter_map(|node| match node {
+                Node::ProcessingInstruction(pi) => Some((pi.target.as_str(), pi.data.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                ("xml", "version=\"1.0\""),
+                ("xml-stylesheet", "href=\"a.xsl\""),
+                ("empty", "")
+            ]
+        );
+        assert_eq!(document.to_mxml(), source);
+    }
+
+    #[test]
+    fn doctype_with_internal_subset() {
+        let source = "<!DOCTYPE note [ <!ENTITY x \"y\"> ]>\n<note>&x;</note>";
+        let document = Document::parse_xml(source, &Dialect::xml()).unwrap();
+        match &document.children[0] {
+            Node::Doctype(doctype) => {
+                assert_eq!(doctype.name, "note");
+                assert_eq!(
+                    doctype.internal_subset.as_deref(),
+                    Some(" <!ENTITY x \"y\"> ")
+                );
+            }
+            node => panic!("expected a doctype, found {node:?}"),
+        }
+        assert!(matches!(&document.children[2], Node::Element(e) if e.name == "note"));
+        assert_eq!(document.to_xml(), source);
+    }
+
+    #[test]
+    fn literal_scope_warnings() {
+        let source = "<a> { {x} <br> {} <b/> {} }";
+        let (_, errors, warnings) = Document::parse_mxml_with_warnings(source, &Dialect::xhtml());
+        assert!(errors.is_empty());
+        let kinds: Vec<&ErrorKind> = warnings.iter().map(Error::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &ErrorKind::LiteralScope,
+                &ErrorKind::ScopeOnVoidElement("br".into()),
+                &ErrorKind::ScopeOnVoidElement("b".into()),
+            ]
+        );
+        assert_eq!(warnings[0].column(), 7);
+    }
+
+    #[test]
+    fn mxml_round_trips_exactly() {
+        let source = "\u{feff}\n  <a>\n{ <b>{x}  <c>\t{ { y } }\n}";
+        let document = Document::parse_mxml(source, &Dialect::xml()).unwrap();
+        assert_eq!(document.to_mxml(), source);
+        assert_eq!(
+            document.to_xml(),
+            "\u{feff}\n  <a> <b>x</b>  <c> { y } </c>\n</a>"
+        );
+        assert_eq!(
+            document.to_xml_keeping_brace_whitespace(),
+            "\u{feff}\n  <a>\n <b>x</b>  <c>\t { y } </c>\n</a>"
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let source = "<a>".repeat(MAX_NESTING_DEPTH + 1);
+        let (_, errors) = Document::parse_xml_recovering(&source, &Dialect::xml());
+        let kinds: Vec<&ErrorKind> = errors.iter().map(Error::kind).collect();
+        assert_eq!(kinds, vec![&ErrorKind::NestingTooDeep]);
+
+        let source = "{".repeat(MAX_NESTING_DEPTH + 1);
+        let (_, errors) = Document::parse_mxml_recovering(&source, &Dialect::xml());
+        let kinds: Vec<&ErrorKind> = errors.iter().map(Error::kind).collect();
+        assert_eq!(kinds, vec![&ErrorKind::NestingTooDeep]);
+    }
+
+    #[test]
+    fn unclosed_scope_error_points_at_brace() {
+        let error = Document::parse_mxml("<a> {\n<b> {}", &Dialect::xml()).unwrap_err();
+        assert_eq!(error.kind(), &ErrorKind::UnclosedScope);
+        assert_eq!((error.line(), error.column()), (1, 5));
+    }
+
+    #[test]
+    fn dialect_rules() {
+        let source = "<UL><li>a<LI>b<BR></ul><Script>x</SCRIPT>";
+        let (mut document, errors) = Document::parse_xml_recovering(source, &Dialect::html5());
+        assert_eq!(errors, vec![]);
+        assert_eq!(
+            document.to_mxml(),
+            "<UL> {<li> {a}<LI> {b<BR>}}<Script> {x}"
+        );
+        lowercase_tag_names(&mut document.children);
+        assert_eq!(
+            document.to_xml(),
+            "<ul><li>a</li><li>b<br></li></ul><script>x</script>"
+        );
+
+        // without the dialect's rules, the end tags don't line up
+        let (_, errors) = Document::parse_xml_recovering(source, &Dialect::xhtml());
+        assert!(!errors.is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+//! Errors produced while converting, pointing at where in the source they happened
+
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// What went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// an end tag that doesn't match the innermost open element
+    MismatchedEndTag { expected: String, found: String },
+    /// an end tag without any open element to close
+    UnmatchedEndTag(String),
+    /// a start tag that is never closed by an end tag
+    UnclosedStartTag(String),
+    /// a `}` without any open scope to close
+    UnmatchedScopeClose,
+    /// a `{` that is never closed by a `}`
+    UnclosedScope,
+    /// elements or scopes nested deeper than `document::MAX_NESTING_DEPTH`
+    NestingTooDeep,
+    /// markup or a brace on the same line as one-line content like `<b>: text`, which
+    /// runs to the end of the line and so can't hold it
+    OneLineInterrupted(String),
+    /// a warning about a `{` without a tag before it, which is kept as text
+    LiteralScope,
+    /// a warning about a `{` after a void element or empty-element tag, which can't
+    /// have content, so the braces are kept as text
+    ScopeOnVoidElement(String),
+    /// a warning about a quoted string after a start tag which isn't one-line content,
+    /// since something other than whitespace follows it on its line, so it's kept as text
+    QuotedTextKept(String),
+}
+
+impl ErrorKind {
+    /// Whether this is only a warning, about something which converts but probably
+    /// isn't what was meant
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::LiteralScope
+                | ErrorKind::ScopeOnVoidElement(_)
+                | ErrorKind::QuotedTextKept(_)
+        )
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::MismatchedEndTag { expected, found } => {
+                write!(
+                    f,
+                    "mismatched end tag, expected `</{expected}>` but found `</{found}>`"
+                )
+            }
+            ErrorKind::UnmatchedEndTag(name) => write!(f, "unmatched end tag `</{name}>`"),
+            ErrorKind::UnclosedStartTag(name) => write!(f, "start tag `<{name}>` is never closed"),
+            ErrorKind::UnmatchedScopeClose => write!(f, "unmatched `}}`"),
+            ErrorKind::UnclosedScope => write!(f, "scope opened here is never closed"),
+            ErrorKind::NestingTooDeep => write!(
+                f,
+                "nested more than {} levels deep",
+                crate::document::MAX_NESTING_DEPTH
+            ),
+            ErrorKind::OneLineInterrupted(name) => write!(
+                f,
+                "the content of `<{name}>:` runs to the end of the line, which can't have markup or braces in it"
+            ),
+            ErrorKind::LiteralScope => {
+                write!(f, "braces without a tag before them are kept as text")
+            }
+            ErrorKind::ScopeOnVoidElement(name) => write!(
+                f,
+                "`<{name}>` can't have content, so the braces after it are kept as text"
+            ),
+            ErrorKind::QuotedTextKept(name) => write!(
+                f,
+                "the quoted string after `<{name}>` doesn't end its line, so it is kept as text rather than being the element's content"
+            ),
+        }
+    }
+}
+
+/// A conversion error (or warning), along with its location in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    // boxed, since errors are rare and this keeps `Result`s small
+    inner: Box<ErrorInner>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ErrorInner {
+    kind: ErrorKind,
+    file: Option<PathBuf>,
+    line: usize,
+    column: usize,
+    span: Range<usize>,
+    /// the full text of the line the span starts on, used for the snippet, if the
+    /// source was at hand
+    source_line: Option<String>,
+    /// how many characters of `source_line` the span covers
+    span_width: usize,
+}
+
+impl Error {
+    /// Create an error of the given kind, locating `span` within `source`
+    pub fn new(kind: ErrorKind, source: &str, span: Range<usize>) -> Self {
+        let start = floor_char_boundary(source, span.start);
+        let before = &source[..start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let source_line = source[line_start..line_end].trim_end_matches('\r');
+        let span_end = floor_char_boundary(source, span.end)
+            .min(line_start + source_line.len())
+            .max(start);
+        let inner = ErrorInner {
+            kind,
+            file: None,
+            line: before.matches('\n').count() + 1,
+            column: source[line_start..start].chars().count() + 1,
+            span_width: source[start..span_end].chars().count(),
+            span,
+            source_line: Some(source_line.to_string()),
+        };
+        Error {
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Create an error of the given kind at a known location, for when the source is no
+    /// longer at hand. It is displayed without a snippet of the source.
+    pub(crate) fn located(kind: ErrorKind, span: Range<usize>, line: usize, column: usize) -> Self {
+        let inner = ErrorInner {
+            kind,
+            file: None,
+            line,
+            column,
+            span,
+            source_line: None,
+            span_width: 0,
+        };
+        Error {
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Attach the path of the file being converted, which is shown when the error is displayed
+    pub fn with_file(mut self, file: impl AsRef<Path>) -> Self {
+        self.inner.file = Some(file.as_ref().to_path_buf());
+        self
+    }
+
+    /// What went wrong
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner.kind
+    }
+
+    /// The file being converted, if known
+    pub fn file(&self) -> Option<&Path> {
+        self.inner.file.as_deref()
+    }
+
+    /// 1-based line number of the start of the offending text
+    pub fn line(&self) -> usize {
+        self.inner.line
+    }
+
+    /// 1-based column (in characters) of the start of the offending text
+    pub fn column(&self) -> usize {
+        self.inner.column
+    }
+
+    /// Byte range of the offending text in the source
+    pub fn span(&self) -> Range<usize> {
+        self.inner.span.clone()
+    }
+}
+
+/// the closest index at or before `index` which is a char boundary in `s`
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    let mut index = index.min(s.len());
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+impl fmt::Display for Error {
+    /// Renders the error like rustc does, with a snippet of the source and a
+    /// caret under the offending text
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = &self.inner;
+        let file = inner
+            .file
+            .as_ref()
+            .map_or_else(|| "<input>".to_string(), |file| file.display().to_string());
+        let line_number = inner.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        let severity = if inner.kind.is_warning() {
+            "warning"
+        } else {
+            "error"
+        };
+        writeln!(f, "{severity}: {}", inner.kind)?;
+        write!(f, "{gutter}--> {file}:{}:{}", inner.line, inner.column)?;
+        let Some(source_line) = &inner.source_line else {
+            return Ok(());
+        };
+
+        // keep tabs in the padding so the carets line up with the snippet
+        let padding: String = source_line
+            .chars()
+            .take(inner.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(inner.span_width.max(1));
+        writeln!(f)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line_number} | {source_line}")?;
+        write!(f, "{gutter} | {padding}{carets}")
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_and_column() {
+        let source = "<a>\n  <b>\n  </c>\n</a>";
+        let start = source.find("</c>").unwrap();
+        let error = Error::new(
+            ErrorKind::MismatchedEndTag {
+                expected: "b".into(),
+                found: "c".into(),
+            },
+            source,
+            start..start + 4,
+        );
+        assert_eq!((error.line(), error.column()), (3, 3));
+    }
+
+    #[test]
+    fn span_outside_source() {
+        let error = Error::new(ErrorKind::UnclosedScope, "é\r\n", 1..100);
+        assert_eq!((error.line(), error.column()), (1, 1));
+        error.to_string();
+    }
+
+    #[test]
+    fn rendered_snippet() {
+        let source = "<a> {\n  text }\n}";
+        let start = source.rfind('}').unwrap();
+        let error = Error::new(ErrorKind::UnmatchedScopeClose, source, start..start + 1)
+            .with_file("page.mxml");
+        let expected = "error: unmatched `}`\n \
+                        --> page.mxml:3:1\n  \
+                        |\n\
+                        3 | }\n  \
+                        | ^";
+        assert_eq!(error.to_string(), expected);
+    }
+
+    #[test]
+    fn rendered_without_source() {
+        let error = Error::located(ErrorKind::UnclosedScope, 10..11, 2, 5);
+        let expected = "error: scope opened here is never closed\n \
+                        --> <input>:2:5";
+        assert_eq!(error.to_string(), expected);
+    }
+}
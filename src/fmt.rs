@@ -0,0 +1,53 @@
+//! Formatting MXML files in place
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use mini_markup::{format_mxml, Dialect};
+
+use crate::args::{Args, Target};
+use crate::{detect, read_input, write_output};
+
+/// Format each of `files`, or stdin to stdout if there are none (or for `-`)
+/// # Returns
+/// whether everything went well: every file could be formatted, and with `check`,
+/// every file was formatted already
+pub fn format_files(
+    files: &[PathBuf],
+    args: &Args,
+    dialect: Option<&Dialect>,
+    check: bool,
+) -> Result<bool> {
+    let stdin = [PathBuf::from("-")];
+    let files = if files.is_empty() { &stdin[..] } else { files };
+
+    let mut ok = true;
+    for file in files {
+        let path = Some(file.as_path()).filter(|file| *file != Path::new("-"));
+        let source = read_input(path)?;
+        let conversion = detect::detect(path, None, &source, Some(Target::Xml), args.html)?;
+        let options = args.options(conversion.dialect(dialect));
+        let formatted = match format_mxml(&source, &options) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                let name = path.unwrap_or_else(|| Path::new("<stdin>"));
+                eprintln!("{}\n", error.with_file(name));
+                ok = false;
+                continue;
+            }
+        };
+        match (check, path) {
+            (true, _) if formatted != source => {
+                println!("{}", path.unwrap_or_else(|| Path::new("<stdin>")).display());
+                ok = false;
+            }
+            (true, _) => {}
+            (false, Some(path)) if formatted != source => fs::write(path, formatted)
+                .with_context(|| format!("failed to write `{}`", path.display()))?,
+            (false, Some(_)) => {}
+            (false, None) => write_output(None, &formatted)?,
+        }
+    }
+    Ok(ok)
+}
@@ -0,0 +1,462 @@
+//! Layout of generated MXML, and canonical formatting of MXML documents
+
+use crate::dialect::Dialect;
+use crate::document::{one_line_element, write_nodes, Document, Element, Node, Render};
+use crate::shorthand;
+use crate::tokenizer::Syntax;
+
+/// elements whose whitespace is significant, so their content is never reformatted
+const PREFORMATTED_ELEMENTS: [&str; 1] = ["pre"];
+
+/// How to lay out the scopes of generated MXML. The default writes `<tag> {` and puts
+/// the `}` wherever the end tag was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MxmlStyle {
+    /// write `<tag> {` rather than `<tag>{`
+    pub space_before_brace: bool,
+    /// put the `{` on the line after the start tag, at the same indentation
+    pub brace_on_next_line: bool,
+    /// put the `}` of a block spanning several lines on a line of its own, if it isn't
+    /// already. The formatter always does this.
+    pub close_on_own_line: bool,
+    /// the indentation for each level of nesting, for lines which are added
+    pub indent: Indent,
+    /// annotate the `}` of blocks spanning at least this many lines with the tag
+    /// name, like `} <!-- /section -->`
+    pub annotate_min_lines: Option<usize>,
+}
+
+impl Default for MxmlStyle {
+    fn default() -> Self {
+        MxmlStyle {
+            space_before_brace: true,
+            brace_on_next_line: false,
+            close_on_own_line: false,
+            indent: Indent::Spaces(4),
+            annotate_min_lines: None,
+        }
+    }
+}
+
+impl MxmlStyle {
+    /// Write what goes between a start tag and the content of its scope
+    pub(crate) fn write_open(&self, out: &mut String, depth: usize) {
+        if self.brace_on_next_line {
+            out.push('\n');
+            self.indent.write(out, depth);
+        } else if self.space_before_brace {
+            out.push(' ');
+        }
+        out.push('{');
+    }
+
+    /// Write the `}` closing the scope of `name`, annotated if the block spans `lines` lines
+    pub(crate) fn write_close(&self, out: &mut String, name: &str, lines: usize) {
+        out.push('}');
+        if self.annotate_min_lines.is_some_and(|min| lines >= min) {
+            out.push_str(" <!-- /");
+            out.push_str(name);
+            out.push_str(" -->");
+        }
+    }
+}
+
+/// One level of indentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+impl Indent {
+    pub(crate) fn write(self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            match self {
+                Indent::Spaces(width) => out.extend(std::iter::repeat_n(' ', width)),
+                Indent::Tabs => out.push('\t'),
+            }
+        }
+    }
+}
+
+/// Remove the comments annotating closing braces, like `} <!-- /section -->`, which
+/// `MxmlStyle::annotate_min_lines` adds, from the nodes and their descendants
+pub(crate) fn remove_closing_annotations(nodes: &mut Vec<Node>) {
+    let mut i = 0;
+    while i < nodes.len() {
+        if let Node::Element(element) = &mut nodes[i] {
+            let Some(children) = &mut element.children else {
+                i += 1;
+                continue;
+            };
+            remove_closing_annotations(children);
+            let annotation = format!("<!-- /{} -->", element.name);
+            let is_annotation =
+                |node: Option<&Node>| matches!(node, Some(Node::Comment(c)) if *c == annotation);
+            if matches!(nodes.get(i + 1), Some(Node::Text(t)) if t == " ")
+                && is_annotation(nodes.get(i + 2))
+            {
+                nodes.drain(i + 1..i + 3);
+            } else if is_annotation(nodes.get(i + 1)) {
+                nodes.remove(i + 1);
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Forget the whitespace elements had before their `{`, so that the style decides it instead
+pub(crate) fn forget_brace_whitespace(nodes: &mut [Node]) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            element.brace_whitespace = None;
+            if let Some(children) = &mut element.children {
+                forget_brace_whitespace(children);
+            }
+        }
+    }
+}
+
+/// Add the line break which `format_document` writes before the `}` of blocks spanning
+/// lines, and at the end of the document, to `nodes` and their descendants, so that an
+/// element at the end of one of their lines is known to be followed by one
+pub(crate) fn add_closing_line_breaks(nodes: &mut Vec<Node>, dialect: &Dialect) {
+    for node in nodes.iter_mut() {
+        if let Node::Element(element) = node {
+            if let Some(children) = &mut element.children {
+                if spans_lines(children) && !is_verbatim(&element.name, children, dialect) {
+                    add_closing_line_breaks(children, dialect);
+                }
+            }
+        }
+    }
+    if let [.., before, Node::Text(text)] = &mut nodes[..] {
+        // a quoted string after a start tag which the line break would turn into the
+        // element's one-line content stays text, with its quote escaped
+        let quoted = one_line_element(std::slice::from_mut(before), dialect).is_some()
+            && shorthand::quoted_mid_line(text)
+            && shorthand::one_line(&format!("{text}\n")).is_some();
+        if quoted {
+            *text = text.replacen('"', "&quot;", 1);
+        }
+    }
+    match nodes.last_mut() {
+        Some(Node::Text(text)) => text.push('\n'),
+        _ => nodes.push(Node::Text("\n".to_string())),
+    }
+}
+
+/// Render the document as canonically formatted MXML. Blocks are indented by their
+/// depth, with `}` on its own line if their content spans more than one line, and
+/// kept inline like `<b> {text}` otherwise. Lines are trimmed, and runs of blank lines
+/// collapsed into one. The dialect decides which elements are `<pre>` elements, whose
+/// content is left alone.
+pub fn format_document(document: &Document, style: &MxmlStyle, dialect: &Dialect) -> String {
+    let mut lines = Lines(vec![(0, String::new())]);
+    write_block(&document.children, 0, &mut lines, style, dialect);
+
+    let mut out = String::new();
+    let mut blank = false;
+    for (depth, line) in &lines.0 {
+        let line = line.trim();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        // blank lines are only kept between lines of the same block
+        if blank && !out.is_empty() && !out.ends_with("{\n") && !line.starts_with('}') {
+            out.push('\n');
+        }
+        blank = false;
+        style.indent.write(&mut out, *depth);
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Whether the lines of `source` end in `\r\n` rather than `\n`, going by its first line
+pub(crate) fn uses_crlf(source: &str) -> bool {
+    source
+        .find('\n')
+        .is_some_and(|end| source[..end].ends_with('\r'))
+}
+
+/// `text` with every line ending in `\r\n`, for sources written that way
+pub(crate) fn with_crlf(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 16);
+    for piece in text.split_inclusive('\n') {
+        match piece.strip_suffix('\n') {
+            Some(line) => {
+                out.push_str(line.strip_suffix('\r').unwrap_or(line));
+                out.push_str("\r\n");
+            }
+            None => out.push_str(piece),
+        }
+    }
+    out
+}
+
+/// Lines of output along with their depth. The last one is the line being written.
+struct Lines(Vec<(usize, String)>);
+
+impl Lines {
+    fn push_str(&mut self, s: &str) {
+        if let Some((_, line)) = self.0.last_mut() {
+            line.push_str(s);
+        }
+    }
+
+    fn new_line(&mut self, depth: usize) {
+        self.0.push((depth, String::new()));
+    }
+}
+
+fn write_block(
+    nodes: &[Node],
+    depth: usize,
+    lines: &mut Lines,
+    style: &MxmlStyle,
+    dialect: &Dialect,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(raw) => {
+                for (i, piece) in raw.split('\n').enumerate() {
+                    if i > 0 {
+                        lines.new_line(depth);
+                    }
+                    lines.push_str(piece);
+                }
+            }
+            Node::Element(element) => write_element(element, node, depth, lines, style, dialect),
+            _ => {
+                // comments and the like are kept as they are, even across lines
+                let mut raw = String::new();
+                write_nodes(std::slice::from_ref(node), &mut raw, &mxml(style), depth);
+                lines.push_str(&raw);
+            }
+        }
+    }
+}
+
+fn write_element(
+    element: &Element,
+    node: &Node,
+    depth: usize,
+    lines: &mut Lines,
+    style: &MxmlStyle,
+    dialect: &Dialect,
+) {
+    let Some(children) = &element.children else {
+        lines.push_str(&element.start_tag);
+        return;
+    };
+    if is_verbatim(&element.name, children, dialect) {
+        let mut raw = String::new();
+        write_nodes(std::slice::from_ref(node), &mut raw, &mxml(style), depth);
+        lines.push_str(&raw);
+        return;
+    }
+
+    lines.push_str(&element.start_tag);
+    if !spans_lines(children) {
+        let inline_style = MxmlStyle {
+            brace_on_next_line: false,
+            ..style.clone()
+        };
+        let mut open = String::new();
+        inline_style.write_open(&mut open, depth);
+        lines.push_str(&open);
+        write_block(children, depth + 1, lines, style, dialect);
+        lines.push_str("}");
+        return;
+    }
+
+    if style.brace_on_next_line {
+        lines.new_line(depth);
+        lines.push_str("{");
+    } else if style.space_before_brace {
+        lines.push_str(" {");
+    } else {
+        lines.push_str("{");
+    }
+    let first_line = lines.0.len();
+    lines.new_line(depth + 1);
+    write_block(children, depth + 1, lines, style, dialect);
+    let block_lines = lines.0.len() - first_line;
+    lines.new_line(depth);
+    let mut close = String::new();
+    style.write_close(&mut close, &element.name, block_lines);
+    lines.push_str(&close);
+}
+
+/// render MXML as it is, for the parts which aren't reformatted
+fn mxml(style: &MxmlStyle) -> Render<'_> {
+    Render {
+        syntax: Syntax::Mxml,
+        style,
+        keep_brace_whitespace: false,
+    }
+}
+
+/// whether the content of the element `name` is written as it is, rather than reformatted
+fn is_verbatim(name: &str, children: &[Node], dialect: &Dialect) -> bool {
+    PREFORMATTED_ELEMENTS
+        .iter()
+        .any(|preformatted| dialect.names_match(preformatted, name))
+        || children
+            .iter()
+            .any(|child| matches!(child, Node::RawText(_)))
+}
+
+/// whether any of `nodes` contains a line break
+fn spans_lines(nodes: &[Node]) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Element(element) => element.children.as_deref().is_some_and(spans_lines),
+        Node::Text(raw) | Node::RawText(raw) | Node::Comment(raw) | Node::CData(raw) => {
+            raw.contains('\n')
+        }
+        Node::ProcessingInstruction(instruction) => instruction.raw.contains('\n'),
+        Node::Doctype(doctype) => doctype.raw.contains('\n'),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, Options, Result, Shorthands};
+
+    fn format_mxml(source: &str, html: bool) -> Result<String> {
+        let dialect = if html {
+            Dialect::html5()
+        } else {
+            Dialect::xml()
+        };
+        let options = Options {
+            dialect,
+            ..Options::default()
+        };
+        crate::format_mxml(source, &options)
+    }
+
+    #[test]
+    fn reindents_blocks() {
+        let source = "<a>{\n<b>   {\n  text\n        more}\n<c> {inline <d> {x}}  \n\n\n<e/>}\n";
+        let expected = "<a> {\n    <b> {\n        text\n        more\n    }\n    <c> {inline <d> {x}}\n\n    <e/>\n}\n";
+        assert_eq!(format_mxml(source, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn literal_braces_escaped() {
+        let source = "<p> {a { b } &lbrkt;}";
+        assert_eq!(
+            format_mxml(source, false).unwrap(),
+            "<p> {a &lbrkt; b &rbrkt; &lbrkt;}\n"
+        );
+    }
+
+    #[test]
+    fn verbatim_content_kept() {
+        let source = "<body> {\n<pre> {\n  keep\n    this}\n  <script> {\n  if (a) { b(); }\n  }\n<!-- a\n  comment -->\n}";
+        let expected = "<body> {\n    <pre> {\n  keep\n    this}\n    <script> {\n  if (a) { b(); }\n  }\n    <!-- a\n  comment -->\n}\n";
+        assert_eq!(format_mxml(source, true).unwrap(), expected);
+
+        // HTML tag names ignore case, but XML ones don't
+        let source = "<PRE> {\n    a\n      b\n}";
+        assert_eq!(
+            format_mxml(source, true).unwrap(),
+            "<PRE> {\n    a\n      b\n}\n"
+        );
+        assert_eq!(
+            format_mxml(source, false).unwrap(),
+            "<PRE> {\n    a\n    b\n}\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_kept() {
+        let source = "<a> {\r\n<b> {x}\r\n<pre> {\r\n  y\r\n}\r\n}\r\n";
+        let expected = "<a> {\r\n    <b> {x}\r\n    <pre> {\r\n  y\r\n}\r\n}\r\n";
+        assert_eq!(format_mxml(source, false).unwrap(), expected);
+        assert_eq!(format_mxml(expected, false).unwrap(), expected);
+        // the first line decides
+        assert_eq!(
+            format_mxml("<a> {x}\n<b/>\r\n", false).unwrap(),
+            "<a> {x}\n<b/>\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let source = std::fs::read_to_string("tests/files/resume.mxml").unwrap();
+        let formatted = format_mxml(&source, true).unwrap();
+        assert_eq!(format_mxml(&formatted, true).unwrap(), formatted);
+    }
+
+    #[test]
+    fn styled_formatting() {
+        let style = MxmlStyle {
+            brace_on_next_line: true,
+            indent: Indent::Tabs,
+            annotate_min_lines: Some(2),
+            ..MxmlStyle::default()
+        };
+        let source = "<a> {\n<b> {x}\n<c> {\ny\nz}}";
+        let expected =
+            "<a>\n{\n\t<b> {x}\n\t<c>\n\t{\n\t\ty\n\t\tz\n\t} <!-- /c -->\n} <!-- /a -->\n";
+        let options = Options {
+            style,
+            remove_annotations: true,
+            ..Options::default()
+        };
+        let formatted = crate::format_mxml(source, &options).unwrap();
+        assert_eq!(formatted, expected);
+        // existing annotations are replaced rather than added to
+        assert_eq!(crate::format_mxml(&formatted, &options).unwrap(), expected);
+    }
+
+    #[test]
+    fn selectors_kept_and_written() {
+        let options = Options {
+            shorthands: Shorthands {
+                selectors: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        let source = "<ul class=\"x\"> {\nli.a#b[title='c d']{x}\n<li id=\"y\">   {y}}";
+        let expected = "ul.x {\n    <li class=\"a\" id=\"b\" title='c d'> {x}\n    li#y {y}\n}\n";
+        assert_eq!(crate::format_mxml(source, &options).unwrap(), expected);
+        // without them, the selector is only text
+        assert_eq!(
+            format_mxml("p.a {x}", false).unwrap(),
+            "p.a &lbrkt;x&rbrkt;\n"
+        );
+    }
+
+    #[test]
+    fn one_line_content_ends_lines() {
+        let options = Options {
+            shorthands: Shorthands {
+                one_line: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        let source = "<ul> {\n<li> {a}\n<li> {b} <li>\"c\" d\n<li> {e}}";
+        let expected = "<ul> {\n    <li> \"a\"\n    <li> {b} <li>\"c\" d\n    <li> \"e\"\n}\n";
+        assert_eq!(crate::format_mxml(source, &options).unwrap(), expected);
+        // a quoted string which only ends its line once `}` goes on a line of its own
+        // stays text
+        let source = "<p> {\n<b>\"c\"}";
+        let expected = "<p> {\n    <b>&quot;c\"\n}\n";
+        assert_eq!(crate::format_mxml(source, &options).unwrap(), expected);
+        assert_eq!(crate::format_mxml(expected, &options).unwrap(), expected);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        assert!(format_mxml("<a> {", false).is_err());
+    }
+}
@@ -1,326 +1,367 @@
-use anyhow::{bail, Result};
-/// Library functions for the mxml-conversion program
-use std::collections::HashSet;
-
-// see https://html.spec.whatwg.org/multipage/syntax.html#void-elements
-// !DOCTYPE is also included, since in raw form it looks like an unclosed html tag
-pub const HTML_VOID_ELEMENTS: [&str; 14] = [
-    "!DOCTYPE", "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
-    "source", "track", "wbr",
-];
-// we will need to construct this into a set every time it is needed, is that worth it?
-const XML_VOID_ELEMENTS: [&str; 1] = [
-    "?xml", // make sure the XML prolog doesn't mess anything up since it isn't closed
-];
-// TODO perhaps we could ignore any tag w/ a name not starting with a conventional a-z letter?
+//! Library functions for the mxml-conversion program
+
+use std::sync::LazyLock;
+
+pub mod dialect;
+pub mod document;
+pub mod error;
+pub mod format;
+pub mod shorthand;
+pub mod stream;
+pub mod tokenizer;
+
+pub use dialect::{Dialect, Dialects, HTML_RAW_TEXT_ELEMENTS, HTML_VOID_ELEMENTS};
+pub use document::{Attribute, Doctype, Document, Element, Node, ProcessingInstruction};
+pub use error::{Error, ErrorKind, Result};
+pub use format::{Indent, MxmlStyle};
+pub use shorthand::Shorthands;
+pub use stream::{convert_stream, convert_to_fmt, convert_to_writer};
 
 /// Convert from MXML (curly brackets) into XML (end tags)
 /// # Params
 /// source - the source MXML as a String
 pub fn mxml_to_xml(source: String) -> Result<String> {
-    let scopes_converted = mxml_scopes_to_xml(source, HashSet::from(XML_VOID_ELEMENTS))?;
-    let escapes_converted = replace_bracket_escapes(scopes_converted);
-    Ok(escapes_converted)
+    mxml_to_xml_str(&source)
+}
+
+/// Convert from MXML (curly brackets) into XML (end tags), borrowing the source
+/// # Params
+/// source - the source MXML
+pub fn mxml_to_xml_str(source: &str) -> Result<String> {
+    convert_built_in(source, Direction::MxmlToXml, false)
 }
 
 /// Convert XML to MXML
 /// # Params
 /// source - the source XML as a String
 pub fn xml_to_mxml(source: String) -> Result<String> {
-    let source = replace_brackets(source);
-    xml_scopes_to_mxml(source, HashSet::from(XML_VOID_ELEMENTS))
+    xml_to_mxml_str(&source)
 }
 
-/// Convert MXML to HTML, being aware of HTML5 void elements
+/// Convert XML to MXML, borrowing the source
+/// # Params
+/// source - the source XML
+pub fn xml_to_mxml_str(source: &str) -> Result<String> {
+    convert_built_in(source, Direction::XmlToMxml, false)
+}
+
+/// Convert MXML to HTML, being aware of HTML5 void elements and raw text elements
 /// # Params
 /// source - the source MXML as a string
 pub fn mxml_to_html(source: String) -> Result<String> {
-    let scopes_converted = mxml_scopes_to_xml(source, HashSet::from(HTML_VOID_ELEMENTS))?;
-    let escapes_converted = replace_bracket_escapes(scopes_converted);
-    Ok(escapes_converted)
+    mxml_to_html_str(&source)
+}
+
+/// Convert MXML to HTML like `mxml_to_html`, borrowing the source
+/// # Params
+/// source - the source MXML
+pub fn mxml_to_html_str(source: &str) -> Result<String> {
+    convert_built_in(source, Direction::MxmlToXml, true)
 }
 
-/// Converts HTML to MXML, being aware of HTML5 void elements
+/// Converts HTML to MXML, being aware of HTML5 void elements and raw text elements
 /// # Params
 /// source - the source HTML as a String
 pub fn html_to_mxml(source: String) -> Result<String> {
-    let source = replace_brackets(source);
-    xml_scopes_to_mxml(source, HashSet::from(HTML_VOID_ELEMENTS))
+    html_to_mxml_str(&source)
 }
 
-/// converts xml scopes to mxml
+/// Converts HTML to MXML like `html_to_mxml`, borrowing the source
 /// # Params
-/// source - xml text
-/// void_element_tags - a set of tags which are allowed to be empty-element tags without `/>` at the end
-fn xml_scopes_to_mxml(source: String, void_element_tags: HashSet<&str>) -> Result<String> {
-    // do a similar thing, parsing tags into stack
-    // append ` {` after every opening tag
-    // upon encountering end tag w/ same tag name as top of stack which should be always?
-    // just replace with `}`
-
-    // How to solve problem of self-closing tags?
-    // - new solution, get the names of the tags which are allowed to not close themselves
-    // - but still be empty-element tags and check for those
-
-    // or just focus on converting well-formed XML? XML requires self-closing tags
-    // to end in `/>`, so it would be possible to check
-    // not so easy with HTML though.
-
-    let mut tag_name_stack: Vec<String> = Vec::new();
-
-    let chars: Vec<char> = source.chars().collect();
-    let mut out = String::new();
-
-    let mut in_closing_tag = false;
-    let mut in_comment = false;
-    for (index, char) in chars.iter().enumerate() {
-        // if inside comment override everything
-        if in_comment {
-            out.push(*char);
-            if *char == '>' && chars[index - 1] == '-' && chars[index - 2] == '-' {
-                in_comment = false;
-            }
-            continue;
-        }
-        // in empty element tag case do default case, no need to open or close any scopes
-        if *char == '>' && chars[index - 1] != '/' {
-            if in_closing_tag {
-                out.push('}'); // the end of the scope
-                in_closing_tag = false;
-            } else if let Some(XMLTag::Start(tag_name)) = find_tag_name_at(&chars, index) {
-                // if the tag is void-element (allowed to be empty element without closing `/>`)
-                // don't add it to stack of tags that need to be closed, don't add curly brace
-                if !void_element_tags.contains(tag_name.as_str()) {
-                    tag_name_stack.push(tag_name);
-                    out.push_str("> {"); // yes this is hardcoded
-                                         // TODO make whitespace customizable?
-                } else {
-                    out.push('>');
-                }
-            } else {
-                bail!(format!(
-                    "Couldn't find well-formed tag around position {}",
-                    index
-                ))
-            }
-        } else if *char == '<' {
-            if chars[index + 1] == '/' {
-                // this assumes all end tags start with `</` and there is no whitespace between < and /
-                // TODO check if this is a reasonable assumption based on XML spec
-                if let Some(XMLTag::End(tag_name)) = find_tag_name_at(&chars, index) {
-                    if let Some(matched_name) = tag_name_stack.pop() {
-                        if tag_name == matched_name {
-                            in_closing_tag = true;
-                        } else {
-                            // TODO: clean this up... or leave a verbose error message?
-                            bail!(format!("Mismatched end tag at position {index}: ...{}...\nExpected {matched_name}, got {tag_name}", chars[index-10..index+10].iter().collect::<String>()))
-                        }
-                    } else {
-                        bail!(format!("Extra unmatched end tag at position {index}"))
-                    }
-                } else {
-                    bail!(format!(
-                        "Couldn't find well-formed tag around position {}",
-                        index
-                    ))
-                }
-            } else if chars[index + 1..index + 4].iter().collect::<String>() == "!--" {
-                in_comment = true;
-                out.push(*char);
-            } else {
-                out.push(*char);
-            }
-        } else if !in_closing_tag {
-            out.push(*char);
-        }
-    }
-    if !tag_name_stack.is_empty() {
-        bail!("Unmatched start tag(s) present")
-    }
+/// source - the source HTML
+pub fn html_to_mxml_str(source: &str) -> Result<String> {
+    convert_built_in(source, Direction::XmlToMxml, true)
+}
 
-    Ok(out)
+/// The direction of a conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// from MXML (curly brackets) into XML (end tags)
+    MxmlToXml,
+    /// from XML (end tags) into MXML (curly brackets)
+    XmlToMxml,
 }
 
-/// converts mxml scopes to xml
+/// Convert in either direction, carrying on past errors to report every problem in the
+/// source at once instead of stopping at the first
 /// # Params
-/// source - xml text
-/// void_element_tags - a set of tags which are allowed to be empty-element tags without `/>` at the end
-fn mxml_scopes_to_xml(source: String, void_element_tags: HashSet<&str>) -> Result<String> {
-    let mut tag_stack: Vec<Option<String>> = Vec::new();
-
-    let chars: Vec<char> = source.chars().collect();
-    let mut out = String::new();
-
-    let mut in_comment = false;
-
-    for (index, char) in chars.iter().enumerate() {
-        // if inside comment override everything
-        if in_comment {
-            out.push(*char);
-            if *char == '>' && chars[index - 1] == '-' && chars[index - 2] == '-' {
-                in_comment = false;
-            }
-            continue;
-        }
-        match char {
-            '{' => {
-                // this is ugly
-                let tag_name = find_tag_name_before_scope(&chars, index);
-                if tag_name.is_some()
-                    && !void_element_tags.contains(tag_name.clone().unwrap().as_str())
-                {
-                    tag_stack.push(tag_name);
-                } else {
-                    // unknown curly braces are ignored, and left as is
-                    out.push(*char);
-                    // put None on the stack to signal to ignore the matching }
-                    tag_stack.push(None);
-                }
-                // we just found a tag, so remove whitespace b/w opening tag and scope opener
-                out = out.trim().to_string(); // this may be inefficient
-            }
-            '}' => match tag_stack.pop() {
-                Some(Some(tag)) => {
-                    out.push_str(format!("</{}>", tag).as_str());
-                }
-                Some(None) => {
-                    out.push('}');
-                }
-                None => {
-                    bail!("Unmatched closing tag")
-                }
-            },
-            '<' => {
-                if chars[index + 1..index + 4].iter().collect::<String>() == "!--" {
-                    in_comment = true;
-                }
-                out.push(*char);
-            }
-            _ => out.push(*char),
-        }
-    }
-    if !tag_stack.is_empty() {
-        bail!("Found unclosed scope(s)")
-    }
+/// source - the source text
+/// direction - which way to convert
+/// html - whether to be aware of HTML5 void elements and raw text elements
+/// # Returns
+/// The converted text, or every error found in the source
+pub fn convert_recovering(
+    source: &str,
+    direction: Direction,
+    html: bool,
+) -> std::result::Result<String, Vec<Error>> {
+    convert_with_options(source, direction, built_in_options(html))
+}
 
-    Ok(out)
+/// Convert like `convert_recovering`, stopping at the first error
+fn convert_built_in(source: &str, direction: Direction, html: bool) -> Result<String> {
+    convert_recovering(source, direction, html).map_err(|mut errors| errors.swap_remove(0))
 }
 
-/// Replaces the custom mxml escape codes `&lbrkt;` and `&rbrkt;` with the characters
-/// `{` and `}` respectively.
-fn replace_bracket_escapes(source: String) -> String {
-    // a manual implementation could be more efficient in one pass as opposed to probably two
-    source.replace("&lbrkt;", "{").replace("&rbrkt;", "}")
+/// The default options with the XML or HTML5 dialect, which are only built once rather
+/// than on every conversion, since the HTML5 dialect takes dozens of allocations
+fn built_in_options(html: bool) -> &'static Options {
+    static XML: LazyLock<Options> = LazyLock::new(Options::default);
+    static HTML5: LazyLock<Options> = LazyLock::new(|| Options {
+        dialect: Dialect::html5(),
+        ..Options::default()
+    });
+    if html {
+        &HTML5
+    } else {
+        &XML
+    }
 }
 
-// Replaces the left and right curly braces with escape codes, inverse to `replace_bracket_escapes`
-fn replace_brackets(source: String) -> String {
-    source.replace('{', "&lbrkt;").replace('}', "&rbrkt;")
+/// Options for converting, beyond which way to convert
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// the void elements, raw text elements and other rules of the markup, XML by default
+    pub dialect: Dialect,
+    /// the layout of the scopes when converting to MXML
+    pub style: MxmlStyle,
+    /// when converting to XML, keep the whitespace between start tags and their `{`,
+    /// so that the braces are the only thing replaced
+    pub keep_brace_whitespace: bool,
+    /// write tag names in lowercase, for HTML written in mixed case like `<DIV>...</div>`
+    pub lowercase_tag_names: bool,
+    /// when converting to XML (or formatting MXML), remove comments annotating the `}` of
+    /// a scope with its tag name, like `} <!-- /section -->`, which
+    /// `MxmlStyle::annotate_min_lines` adds. Off by default, since the same comment may
+    /// just as well have been written by hand.
+    pub remove_annotations: bool,
+    /// the shorthand forms to recognise in MXML, and to write when converting to it
+    pub shorthands: Shorthands,
 }
 
-/// finds the name of the tag appearing before the scope opened by a bracket at
-/// `index`.
+/// Convert like `convert_recovering`, with more control over the output
 /// # Params
-/// `chars`: Vector of characters to search
-/// `index`: index into the given vector, as the position of a `{` character
-/// # Panics
-/// If `chars[index]` is not `{`, panics
-fn find_tag_name_before_scope(chars: &[char], index: usize) -> Option<String> {
-    assert!(chars[index] == '{');
-    let mut index = index - 1;
-    while index > 0 && chars[index].is_whitespace() {
-        index -= 1;
-    }
-    // the first non whitespace character upon walking backwards should be the tag closing right angle bracket
-    if chars[index] != '>' {
-        // this indicates either unclosed tag before the scope, or no tag before the scope
-        return None;
-    }
-    // otherwise go ahead and find the tag name, which should be the first segment of characters
-    // after the opening `<`, before the first space (or the closing `>` if no space/attrs)
-    match find_tag_name_at(chars, index) {
-        Some(XMLTag::Start(s)) => Some(s), // only take start tags
-        _ => None,                         // reject if it was a self-closing or end tag!
+/// source - the source text
+/// direction - which way to convert
+/// options - how to convert
+/// # Returns
+/// The converted text, or every error found in the source
+pub fn convert_with_options(
+    source: &str,
+    direction: Direction,
+    options: &Options,
+) -> std::result::Result<String, Vec<Error>> {
+    let (converted, errors) = stream::convert_to_string(source, direction, options);
+    if errors.is_empty() {
+        Ok(converted)
+    } else {
+        Err(errors)
     }
 }
 
-/// Find the name of the tag given either the index of the starting angle bracket
-/// or the ending angle bracket
+/// Check the source for problems without converting it. MXML is also checked for braces
+/// which will be kept as literal text, which are reported as warnings.
 /// # Params
-/// chars - a slice of characters to search in for the tag name
-/// index - the starting index to look from
-/// # Preconditions
-/// `index` is the index of either the starting `<` or ending `>` of the tag
-fn find_tag_name_at(chars: &[char], index: usize) -> Option<XMLTag> {
-    let mut index = index;
-    if chars[index] == '>' {
-        while index > 0 && chars[index] != '<' {
-            index -= 1;
+/// source - the source text
+/// direction - which way the source would be converted
+/// options - the dialect of the markup, and the shorthands MXML may use
+/// # Returns
+/// Every error and warning, in the order they appear in the source
+pub fn check(source: &str, direction: Direction, options: &Options) -> Vec<Error> {
+    let dialect = &options.dialect;
+    let mut problems = match direction {
+        Direction::MxmlToXml => {
+            let (_, mut errors, warnings) =
+                Document::parse_mxml_with_shorthands(source, dialect, &options.shorthands);
+            errors.extend(warnings);
+            errors
         }
+        Direction::XmlToMxml => Document::parse_xml_recovering(source, dialect).1,
+    };
+    problems.sort_by_key(|problem| problem.span().start);
+    problems
+}
+
+/// Format MXML canonically, reindenting blocks by their depth and writing braces without
+/// a tag before them as `&lbrkt;` and `&rbrkt;`. See `format::format_document`. Lines end
+/// in `\r\n` if the first line of the source does.
+/// # Params
+/// source - the source MXML
+/// options - the dialect of the markup, the shorthands to read and write, and the layout
+/// of the scopes. `}` always goes on its own line after multi-line blocks.
+pub fn format_mxml(source: &str, options: &Options) -> Result<String> {
+    let (mut document, errors, _) =
+        Document::parse_mxml_with_shorthands(source, &options.dialect, &options.shorthands);
+    if let Some(error) = errors.into_iter().next() {
+        return Err(error);
     }
-    if chars[index] != '<' {
-        return None;
+    if options.remove_annotations {
+        format::remove_closing_annotations(&mut document.children);
     }
-    let mut index_space = index;
-    while !chars[index_space].is_whitespace() && chars[index_space] != '>' {
-        index_space += 1;
+    format::forget_brace_whitespace(&mut document.children);
+    // braces in MXML text can only have come from literal scopes
+    replace_in_nodes(&mut document.children, |text| {
+        text.replace('{', "&lbrkt;").replace('}', "&rbrkt;")
+    });
+    if options.shorthands.one_line {
+        format::add_closing_line_breaks(&mut document.children, &options.dialect);
     }
-    if chars[index + 1] == '/' {
-        Some(XMLTag::End(chars[index + 2..index_space].iter().collect()))
-    } else if chars[index_space - 1] == '/' {
-        Some(XMLTag::EmptyElement(
-            chars[index + 1..index_space - 1].iter().collect(),
-        ))
+    write_shorthands(&mut document.children, options);
+    let formatted = format::format_document(&document, &options.style, &options.dialect);
+    if format::uses_crlf(source) {
+        Ok(format::with_crlf(&formatted))
     } else {
-        Some(XMLTag::Start(
-            chars[index + 1..index_space].iter().collect(),
-        ))
+        Ok(formatted)
+    }
+}
+
+/// Write elements in the shorthand forms chosen in `options`, wherever they fit. Content
+/// on one line takes precedence over selectors, which need a scope.
+fn write_shorthands(nodes: &mut [Node], options: &Options) {
+    if options.shorthands.one_line {
+        shorthand::write_one_line(nodes);
+    }
+    if options.shorthands.selectors {
+        shorthand::write_selectors(nodes, &options.dialect);
+    }
+}
+
+/// Applies `replace` to every text node, which is the only place braces mean something
+/// different in MXML. Tags, comments, raw text elements, CDATA sections, processing
+/// instructions and doctypes are all recognised before braces are, so they are left alone.
+fn replace_in_nodes(nodes: &mut [Node], replace: fn(&str) -> String) {
+    for node in nodes {
+        match node {
+            Node::Element(element) => {
+                if let Some(children) = &mut element.children {
+                    replace_in_nodes(children, replace);
+                }
+            }
+            Node::Text(raw) => *raw = replace(raw),
+            Node::RawText(_)
+            | Node::Comment(_)
+            | Node::CData(_)
+            | Node::ProcessingInstruction(_)
+            | Node::Doctype(_) => {}
+        }
     }
 }
 
-enum XMLTag {
-    Start(String),
-    End(String),
-    EmptyElement(String),
+/// Replaces the custom mxml escape codes `&lbrkt;` and `&rbrkt;` with the characters
+/// `{` and `}` respectively. An escape code can itself be escaped by writing `&amp;`
+/// in place of its `&`, e.g. `&amp;lbrkt;` for a literal `&lbrkt;`.
+pub(crate) fn replace_bracket_escapes(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let skip = match bracket_escape_depth(rest) {
+            Some(0) => {
+                result.push(if rest.starts_with("&l") { '{' } else { '}' });
+                "&lbrkt;".len()
+            }
+            // drop one level of escaping
+            Some(_) => {
+                result.push('&');
+                "&amp;".len()
+            }
+            None => {
+                result.push('&');
+                1
+            }
+        };
+        rest = &rest[skip..];
+    }
+    result.push_str(rest);
+    result
+}
+
+// Replaces the left and right curly braces with escape codes, inverse to `replace_bracket_escapes`
+pub(crate) fn replace_brackets(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    for (i, c) in source.char_indices() {
+        match c {
+            '{' => result.push_str("&lbrkt;"),
+            '}' => result.push_str("&rbrkt;"),
+            // escape codes already in the text get escaped themselves so they survive
+            '&' if bracket_escape_depth(&source[i..]).is_some() => result.push_str("&amp;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// If `s` starts with a bracket escape code, like `&lbrkt;` or `&amp;amp;rbrkt;`
+/// # Returns
+/// how many times the escape code is itself escaped, or `None` if `s` doesn't start with one
+fn bracket_escape_depth(s: &str) -> Option<usize> {
+    let mut rest = s.strip_prefix('&')?;
+    let mut depth = 0;
+    while let Some(after) = rest.strip_prefix("amp;") {
+        rest = after;
+        depth += 1;
+    }
+    (rest.starts_with("lbrkt;") || rest.starts_with("rbrkt;")).then_some(depth)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
 
     use crate::*;
 
     #[test]
     fn bracket_escapes_single() {
         let source = "&lbrkt;".to_string();
-        assert_eq!("{", replace_bracket_escapes(source))
+        assert_eq!("{", replace_bracket_escapes(&source))
     }
 
     #[test]
     fn bracket_escapes_multiple() {
         let source = "&rbrkt;  &lbrkt; &lbrkt;".to_string();
-        assert_eq!("}  { {", replace_bracket_escapes(source))
+        assert_eq!("}  { {", replace_bracket_escapes(&source))
     }
 
     #[test]
     fn bracket_escapes_with_other_characters() {
         let source = "&lbrkt; abcdefg&rbrkt;".to_string();
-        assert_eq!("{ abcdefg}", replace_bracket_escapes(source))
+        assert_eq!("{ abcdefg}", replace_bracket_escapes(&source))
     }
 
     #[test]
     fn brackets_replaced_by_escapes() {
         let source = "{".to_string();
-        assert_eq!("&lbrkt;", replace_brackets(source))
+        assert_eq!("&lbrkt;", replace_brackets(&source))
     }
 
     #[test]
     fn replace_brackets_inverse_of_replace_escapes() {
         let source = "&lbrkt; abcdefg&rbrkt;".to_string();
+        assert_eq!(source, replace_brackets(&replace_bracket_escapes(&source)))
+    }
+
+    #[test]
+    fn escaped_bracket_escapes() {
         assert_eq!(
-            source,
-            replace_brackets(replace_bracket_escapes(source.clone()))
-        )
+            replace_brackets("&lbrkt; &amp;rbrkt; &amp; {"),
+            "&amp;lbrkt; &amp;amp;rbrkt; &amp; &lbrkt;"
+        );
+        assert_eq!(
+            replace_bracket_escapes("&amp;lbrkt; &amp;amp;rbrkt; &amp; &lbrkt;"),
+            "&lbrkt; &amp;rbrkt; &amp; {"
+        );
+        assert_eq!(
+            replace_bracket_escapes("&lbrkt &amp;lbrkt &"),
+            "&lbrkt &amp;lbrkt &"
+        );
+    }
+
+    #[test]
+    fn braces_only_escaped_in_text() {
+        let xml = "<a title=\"{x}\"><!-- { --> {y} &lbrkt;</a>";
+        let mxml = "<a title=\"{x}\"> {<!-- { --> &lbrkt;y&rbrkt; &amp;lbrkt;}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
     }
 
     #[test]
@@ -328,31 +369,34 @@ mod tests {
         let source = "<tagname> {}";
         let expected = "<tagname></tagname>";
         assert_eq!(
-            mxml_scopes_to_xml(source.into(), HashSet::new()).unwrap(),
+            convert_built_in(source, Direction::MxmlToXml, false).unwrap(),
             expected
         );
     }
 
+    /// the name of the first element in the MXML `source` which has a scope
+    fn scoped_element_name(source: &str) -> Option<String> {
+        let document = Document::parse_mxml(source, &Dialect::xml()).unwrap();
+        document.children.into_iter().find_map(|node| match node {
+            Node::Element(element) if element.children.is_some() => Some(element.name),
+            _ => None,
+        })
+    }
+
     #[test]
     fn find_tag_name_simple() {
-        let chars: Vec<char> = "<banana> {".chars().collect();
-        assert_eq!("banana", find_tag_name_before_scope(&chars, 9).unwrap());
+        assert_eq!("banana", scoped_element_name("<banana> {}").unwrap());
     }
 
     #[test]
     fn find_tag_name_with_attrs() {
-        let chars: Vec<char> =
-            "<div style=\"border-width: 10px, border-radius: 2px\" on_click=stuff> {"
-                .chars()
-                .collect();
-        let index = chars.len() - 1;
-        assert_eq!("div", find_tag_name_before_scope(&chars, index).unwrap());
+        let source = "<div style=\"border-width: 10px, border-radius: 2px\" on_click=stuff> {}";
+        assert_eq!("div", scoped_element_name(source).unwrap());
     }
 
     #[test]
     fn find_tag_name_none_simple() {
-        let chars: Vec<char> = "text {".chars().collect();
-        assert!(find_tag_name_before_scope(&chars, 5).is_none());
+        assert!(scoped_element_name("text {}").is_none());
     }
 
     #[test]
@@ -360,7 +404,7 @@ mod tests {
         let source = "<tagname></tagname>";
         let expected = "<tagname> {}";
         assert_eq!(
-            xml_scopes_to_mxml(source.into(), HashSet::new()).unwrap(),
+            convert_built_in(source, Direction::XmlToMxml, false).unwrap(),
             expected
         );
     }
@@ -388,6 +432,152 @@ mod tests {
         assert_eq!(html_to_mxml(source.into()).unwrap(), expected);
     }
 
+    #[test]
+    fn html_to_mxml_implied_end_tags() {
+        let source = "<ul>\n  <li>one\n  <li>two\n</ul>\n<p>a\n<p>b\n<div>c</div>";
+        let expected = "<ul> {\n  <li> {one}\n  <li> {two}\n}\n<p> {a}\n<p> {b}\n<div> {c}";
+        assert_eq!(html_to_mxml(source.into()).unwrap(), expected);
+
+        let table = "<table><tr><th>a<td>b<tr><td>c</table>";
+        let expected = "<table> {<tr> {<th> {a}<td> {b}}<tr> {<td> {c}}}";
+        assert_eq!(html_to_mxml(table.into()).unwrap(), expected);
+
+        let select = "<select><optgroup><option>a<option>b<optgroup><option>c</select>";
+        let expected = "<select> {<optgroup> {<option> {a}<option> {b}}<optgroup> {<option> {c}}}";
+        assert_eq!(html_to_mxml(select.into()).unwrap(), expected);
+
+        // elements inside which close along with the element
+        let nested = "<ul><li><p>a<li>b</ul><dl><dt><p>x<dd>y</dl>";
+        let expected = "<ul> {<li> {<p> {a}}<li> {b}}<dl> {<dt> {<p> {x}}<dd> {y}}";
+        assert_eq!(html_to_mxml(nested.into()).unwrap(), expected);
+        let cells = "<table><tr><td><p>a<td>b</table>";
+        let expected = "<table> {<tr> {<td> {<p> {a}}<td> {b}}}";
+        assert_eq!(html_to_mxml(cells.into()).unwrap(), expected);
+
+        // the end tags come back when converting to HTML
+        let html = mxml_to_html(html_to_mxml(table.into()).unwrap()).unwrap();
+        assert_eq!(
+            html,
+            "<table><tr><th>a</th><td>b</td></tr><tr><td>c</td></tr></table>"
+        );
+    }
+
+    #[test]
+    fn html_implied_end_tags_need_html() {
+        // only elements with optional end tags may be left open, and not in XML
+        assert!(html_to_mxml("<p>a<b>b</p>".into()).is_err());
+        assert!(xml_to_mxml("<ul><li>a</ul>".into()).is_err());
+    }
+
+    #[test]
+    fn html_tag_names_ignore_case() {
+        let source = "<DIV>a<BR><Img src=x>b</div><P>c";
+        let expected = "<DIV> {a<BR><Img src=x>b}<P> {c}";
+        assert_eq!(html_to_mxml(source.into()).unwrap(), expected);
+        assert!(xml_to_mxml(source.into()).is_err());
+
+        let options = Options {
+            dialect: Dialect::html5(),
+            lowercase_tag_names: true,
+            remove_annotations: true,
+            ..Options::default()
+        };
+        let lowercased = convert_with_options(source, Direction::XmlToMxml, &options);
+        assert_eq!(lowercased.unwrap(), "<div> {a<br><img src=x>b}<p> {c}");
+        let mxml = "<UL> {<Li> {x}} <!-- /UL -->";
+        let lowercased = convert_with_options(mxml, Direction::MxmlToXml, &options);
+        assert_eq!(lowercased.unwrap(), "<ul><li>x</li></ul>");
+    }
+
+    #[test]
+    fn shorthand_selectors() {
+        let options = Options {
+            dialect: Dialect::html5(),
+            shorthands: Shorthands {
+                selectors: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        let mxml = "div.card#main {\n  <p> {a}\n  ul.list[role=list] {li.item {b}}\n}";
+        let html = "<div class=\"card\" id=\"main\">\n  <p>a</p>\n  <ul class=\"list\" role=\"list\"><li class=\"item\">b</li></ul>\n</div>";
+        let converted = convert_with_options(mxml, Direction::MxmlToXml, &options);
+        assert_eq!(converted.unwrap(), html);
+        // only elements whose attributes are class and id are written as selectors
+        let back = convert_with_options(html, Direction::XmlToMxml, &options);
+        let expected =
+            "div.card#main {\n  <p> {a}\n  <ul class=\"list\" role=\"list\"> {li.item {b}}\n}";
+        assert_eq!(back.unwrap(), expected);
+
+        // a selector can't follow a word, which it would run into
+        let html = "<p>a<b class=\"x\">b</b> <i class=\"y\">c</i></p>";
+        let converted = convert_with_options(html, Direction::XmlToMxml, &options);
+        assert_eq!(converted.unwrap(), "<p> {a<b class=\"x\"> {b} i.y {c}}");
+        // classes and ids can't start with a digit, so numbers stay text
+        for text in ["see v1.2 {x}", "a.1 {x}"] {
+            let converted = convert_with_options(text, Direction::MxmlToXml, &options);
+            assert_eq!(converted.unwrap(), text);
+        }
+        // and without the option, selectors are only text
+        assert_eq!(mxml_to_html("p.a {x}".into()).unwrap(), "p.a {x}");
+    }
+
+    #[test]
+    fn shorthand_one_line() {
+        let options = Options {
+            dialect: Dialect::html5(),
+            shorthands: Shorthands {
+                one_line: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        let mxml = "<ul> {\n  <li> \"Eager to learn\"\n  <li>: a &lbrkt;b&rbrkt; \"c\"\n  <li> {<b> \"d\"\n  e}\n}";
+        let html = "<ul>\n  <li>Eager to learn</li>\n  <li>a {b} \"c\"</li>\n  <li><b>d</b>\n  e</li>\n</ul>";
+        let converted = convert_with_options(mxml, Direction::MxmlToXml, &options);
+        assert_eq!(converted.unwrap(), html);
+        // only text which can be quoted is written on one line
+        let back = convert_with_options(html, Direction::XmlToMxml, &options);
+        let expected = "<ul> {\n  <li> \"Eager to learn\"\n  <li> {a &lbrkt;b&rbrkt; \"c\"}\n  <li> {<b> \"d\"\n  e}\n}";
+        assert_eq!(back.unwrap(), expected);
+
+        // a quoted string has to end its line, so prose starting with one stays text
+        let mxml = "<p>\"Hi\" she said <b>\"no\"</b>";
+        let converted = convert_with_options(mxml, Direction::MxmlToXml, &options);
+        assert_eq!(converted.unwrap(), mxml);
+        let problems = check(mxml, Direction::MxmlToXml, &options);
+        let kinds: Vec<_> = problems.iter().map(Error::kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                &ErrorKind::QuotedTextKept("p".into()),
+                &ErrorKind::QuotedTextKept("b".into())
+            ]
+        );
+        // and it's only written for elements followed by a line break
+        let html = "<p><b>a</b> <i>b</i>\n<i>c</i></p>";
+        let back = convert_with_options(html, Direction::XmlToMxml, &options);
+        assert_eq!(back.unwrap(), "<p> {<b> {a} <i> \"b\"\n<i> {c}}");
+
+        // the colon form runs to the end of the line, so markup or braces can't be on it
+        for mxml in ["<li>: a {b} c\n", "<li>: a <b>x</b> c"] {
+            let errors = convert_with_options(mxml, Direction::MxmlToXml, &options).unwrap_err();
+            let (_, tree_errors, _) =
+                Document::parse_mxml_with_shorthands(mxml, &options.dialect, &options.shorthands);
+            for errors in [errors, tree_errors] {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(
+                    errors[0].kind(),
+                    &ErrorKind::OneLineInterrupted("li".into())
+                );
+                assert_eq!(errors[0].column(), 9);
+            }
+        }
+
+        // without the option, the text is left as it is
+        assert_eq!(mxml_to_html("<b>: x".into()).unwrap(), "<b>: x");
+    }
+
     #[test]
     fn xml_to_mxml_comments_ignored() {
         let source = "<!-- <this would=\"be a tag\"></this but it's in a comment> -->";
@@ -400,15 +590,171 @@ mod tests {
         assert_eq!(mxml_to_xml(source.into()).unwrap(), source);
     }
 
+    #[test]
+    fn angle_brackets_in_attribute_values() {
+        let xml = "<a title=\"x > y\"><div data-expr=\"a<b\"></div></a>";
+        let mxml = "<a title=\"x > y\"> {<div data-expr=\"a<b\"> {}}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
+    }
+
+    #[test]
+    fn slash_in_attribute_value_is_not_self_closing() {
+        let xml = "<a href=\"/path/\">link</a>";
+        let mxml = "<a href=\"/path/\"> {link}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
+    }
+
+    #[test]
+    fn convert_recovering_reports_all_errors() {
+        let source = "<a> {}}\n<b> {";
+        let errors = convert_recovering(source, Direction::MxmlToXml, false).unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn convert_recovering_html() {
+        let source = "<br>\n<p>text</p>";
+        let converted = convert_recovering(source, Direction::XmlToMxml, true).unwrap();
+        assert_eq!(converted, "<br>\n<p> {text}");
+    }
+
+    #[test]
+    fn html_raw_text_passed_through() {
+        let html = "<script>if (a < b) { f(\"</p>\"); }</script>";
+        let mxml = "<script> {if (a < b) { f(\"</p>\"); }}";
+        assert_eq!(html_to_mxml(html.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_html(mxml.into()).unwrap(), html);
+    }
+
+    #[test]
+    fn html_raw_text_with_unbalanced_braces_keeps_end_tag() {
+        let html = "<p> <script>let s = \"}\";</script> </p>";
+        let mxml = "<p> { <script>let s = \"}\";</script> }";
+        assert_eq!(html_to_mxml(html.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_html(mxml.into()).unwrap(), html);
+    }
+
+    #[test]
+    fn html_raw_text_escapes_left_alone() {
+        let mxml = "<style> {p::before { content: \"&lbrkt;\" }}";
+        let html = "<style>p::before { content: \"&lbrkt;\" }</style>";
+        assert_eq!(mxml_to_html(mxml.into()).unwrap(), html);
+    }
+
+    #[test]
+    fn cdata_left_alone() {
+        let xml = "<a><![CDATA[ </a> { &lbrkt; } ]]></a>";
+        let mxml = "<a> {<![CDATA[ </a> { &lbrkt; } ]]>}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
+    }
+
+    #[test]
+    fn processing_instructions_preserved() {
+        let xml = "<?xml version=\"1.0\"?>\n<?xml-stylesheet href=\"{a}.xsl\"?>\n<a></a>";
+        let mxml = "<?xml version=\"1.0\"?>\n<?xml-stylesheet href=\"{a}.xsl\"?>\n<a> {}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
+    }
+
+    #[test]
+    fn doctype_internal_subset_preserved() {
+        let xml = "<!DOCTYPE note [\n  <!ENTITY brace \"{\">\n]>\n<note>&brace;</note>";
+        let mxml = "<!DOCTYPE note [\n  <!ENTITY brace \"{\">\n]>\n<note> {&brace;}";
+        assert_eq!(xml_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), xml);
+        assert_eq!(html_to_mxml(xml.into()).unwrap(), mxml);
+        assert_eq!(mxml_to_html(mxml.into()).unwrap(), xml);
+    }
+
+    #[test]
+    fn check_reports_errors_and_warnings_in_order() {
+        let html = Options {
+            dialect: Dialect::html5(),
+            ..Options::default()
+        };
+        let problems = check("<p> {<img> {}} }", Direction::MxmlToXml, &html);
+        let kinds: Vec<&ErrorKind> = problems.iter().map(Error::kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &ErrorKind::ScopeOnVoidElement("img".into()),
+                &ErrorKind::UnmatchedScopeClose
+            ]
+        );
+        assert!(check("<p> {<img> {}}", Direction::MxmlToXml, &Options::default()).is_empty());
+    }
+
+    #[test]
+    fn mxml_output_style() {
+        let xml = "<section>\n  <p>a\n  b</p>\n</section>";
+        let style = MxmlStyle {
+            space_before_brace: false,
+            close_on_own_line: true,
+            indent: Indent::Tabs,
+            annotate_min_lines: Some(3),
+            ..MxmlStyle::default()
+        };
+        let mxml = "<section>{\n  <p>{a\n  b\n\t}\n} <!-- /section -->";
+        let options = Options {
+            style,
+            ..Options::default()
+        };
+        let converted = convert_with_options(xml, Direction::XmlToMxml, &options);
+        assert_eq!(converted.unwrap(), mxml);
+        // the annotation is only carried over into XML if it isn't asked to be removed
+        let back = convert_recovering(mxml, Direction::MxmlToXml, false).unwrap();
+        assert_eq!(
+            back,
+            "<section>\n  <p>a\n  b\n\t</p>\n</section> <!-- /section -->"
+        );
+        let options = Options {
+            remove_annotations: true,
+            ..Options::default()
+        };
+        let back = convert_with_options(mxml, Direction::MxmlToXml, &options).unwrap();
+        assert_eq!(back, "<section>\n  <p>a\n  b\n\t</p>\n</section>");
+    }
+
+    #[test]
+    fn brace_on_next_line() {
+        let style = MxmlStyle {
+            brace_on_next_line: true,
+            indent: Indent::Spaces(2),
+            ..MxmlStyle::default()
+        };
+        let options = Options {
+            style,
+            ..Options::default()
+        };
+        let converted =
+            convert_with_options("<a>\n  <b>x</b>\n</a>", Direction::XmlToMxml, &options);
+        assert_eq!(converted.unwrap(), "<a>\n{\n  <b>\n  {x}\n}");
+    }
+
+    #[test]
+    fn document_whitespace_preserved() {
+        let mxml = "\u{feff}  \n<a>\n  {x}\n";
+        assert_eq!(mxml_to_xml(mxml.into()).unwrap(), "\u{feff}  \n<a>x</a>\n");
+        let options = Options {
+            keep_brace_whitespace: true,
+            ..Options::default()
+        };
+        let converted = convert_with_options(mxml, Direction::MxmlToXml, &options);
+        assert_eq!(converted.unwrap(), "\u{feff}  \n<a>\n  x</a>\n");
+    }
+
     #[test]
     fn mxml_to_xml_unclosed_error() {
         let source = "<tag attr=a> { unclosed";
-        assert!(mxml_scopes_to_xml(source.into(), HashSet::new()).is_err());
+        assert!(convert_built_in(source, Direction::MxmlToXml, false).is_err());
     }
 
     #[test]
     fn xml_to_mxml_unclosed_error() {
-        let source = "<tag attr= a><inner stuff></inner>".to_string();
-        assert!(xml_scopes_to_mxml(source, HashSet::new()).is_err());
+        let source = "<tag attr= a><inner stuff></inner>";
+        assert!(convert_built_in(source, Direction::XmlToMxml, false).is_err());
     }
 }
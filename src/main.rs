@@ -1,30 +1,64 @@
 use anyhow::{Context, Result};
-use args::Args;
+use args::{Args, Command};
 use clap::Parser;
-use mini_markup::{html_to_mxml, mxml_to_html, mxml_to_xml, xml_to_mxml};
+use mini_markup::{convert_with_options, Direction};
 use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
 
 mod args;
+mod batch;
+mod check;
+mod detect;
+mod dialect_file;
+mod fmt;
+mod watch;
 
 struct Printer {
     verbose: bool,
 }
 
+// progress goes to stderr, so that it doesn't end up mixed into output written to stdout
 impl Printer {
     fn print(&self, s: &str) {
         // print if not silenced
         if self.verbose {
-            print!("{s}");
+            eprint!("{s}");
         }
     }
 
     fn println(&self, s: &str) {
         if self.verbose {
-            println!("{s}");
+            eprintln!("{s}");
         }
     }
 }
 
+/// Read the whole input file, or stdin if there is no file
+fn read_input(path: Option<&Path>) -> Result<String> {
+    match path {
+        Some(path) => fs::read_to_string(path).context("failed to read file"),
+        None => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .context("failed to read stdin")?;
+            Ok(input)
+        }
+    }
+}
+
+/// Write the output to a file, or stdout if there is no file
+fn write_output(path: Option<&Path>, output: &str) -> Result<()> {
+    match path {
+        Some(path) => fs::write(path, output).context("Failed to write to file"),
+        None => io::stdout()
+            .lock()
+            .write_all(output.as_bytes())
+            .context("Failed to write to stdout"),
+    }
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
@@ -32,36 +66,81 @@ fn main() -> Result<()> {
         verbose: args.verbose,
     };
     p.println("Program started, arguments parsed");
+    let dialect = dialect_file::choose(args.dialect.as_deref(), &args.dialect_file)?;
+
+    match &args.command {
+        Some(Command::Watch {
+            input_dir,
+            output_dir,
+        }) => {
+            let batch = batch::Batch::new(&args, dialect)?;
+            return watch::watch(&batch, input_dir, output_dir);
+        }
+        Some(Command::Check {
+            files,
+            deny_warnings,
+        }) => {
+            if !check::check_files(files, &args, dialect.as_ref(), *deny_warnings)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Fmt { files, check }) => {
+            if !fmt::format_files(files, &args, dialect.as_ref(), *check)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if let Some(input_dir) = args.input_path().filter(|path| path.is_dir()) {
+        let output_dir = args
+            .output_path()
+            .context("converting a directory needs an output directory")?;
+        let batch = batch::Batch::new(&args, dialect)?;
+        if !batch.run(input_dir, output_dir)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     p.print("Reading file... ");
 
-    let file_string = fs::read_to_string(&args.input_file).context("failed to read file")?;
+    let file_string = read_input(args.input_path())?;
 
     p.println("File read success");
     p.print("Performing conversion... ");
 
-    let function = match args.target {
-        Some(args::Target::Mxml) => {
-            if args.html {
-                html_to_mxml
-            } else {
-                xml_to_mxml
-            }
-        }
-        Some(args::Target::Xml) | None => {
-            if args.html {
-                mxml_to_html
-            } else {
-                mxml_to_xml
+    let conversion = detect::detect(
+        args.input_path(),
+        args.output_path(),
+        &file_string,
+        args.target,
+        args.html,
+    )?;
+    p.print(match conversion.direction {
+        Direction::MxmlToXml => "(MXML to XML",
+        Direction::XmlToMxml => "(XML to MXML",
+    });
+    let options = args.options(conversion.dialect(dialect.as_ref()));
+    p.print(&format!(", {}) ", options.dialect.name));
+
+    let result = match convert_with_options(&file_string, conversion.direction, &options) {
+        Ok(result) => result,
+        Err(errors) => {
+            for error in &errors {
+                let file = args.input_path().unwrap_or_else(|| Path::new("<stdin>"));
+                eprintln!("{}\n", error.clone().with_file(file));
             }
+            eprintln!("conversion failed with {} error(s)", errors.len());
+            std::process::exit(1);
         }
     };
-
-    let result = function(file_string).context("conversion failed")?;
     p.println("Conversion success");
 
     p.println("Attempting file write");
-    fs::write(args.output_file, result).context("Failed to write to file")?;
+    write_output(args.output_path(), &result)?;
     p.println("Sucess!");
 
     Ok(())
@@ -0,0 +1,455 @@
+//! Optional shorthand forms of MXML, which save typing out whole start tags and scopes
+
+use crate::dialect::Dialect;
+use crate::document::{trim_whitespace_end, Attribute, Element, Node};
+
+/// Which shorthand forms to recognise when converting from MXML, and to write when
+/// converting to it. None of them are used by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Shorthands {
+    /// selectors like `div.card#main[role=list] {`, standing for the start tag
+    /// `<div class="card" id="main" role="list">`. Elements whose only attributes are
+    /// `class` and `id` are written this way.
+    pub selectors: bool,
+    /// content on the same line as a start tag, standing for all of the element's content:
+    /// a quoted string like `<li> "text"`, or a colon and the rest of the line like
+    /// `<b>: text`. Elements whose only content is a short line of text are written as
+    /// quoted strings.
+    pub one_line: bool,
+}
+
+/// The most characters of text written as one-line content
+const ONE_LINE_MAX_CHARS: usize = 80;
+
+/// A selector standing for a start tag, like `div.card#main[role=list]`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Selector<'a> {
+    pub name: &'a str,
+    /// the attributes in the order they are first given, with every class gathered into
+    /// one `class` attribute, and the quote around each value
+    attributes: Vec<(&'a str, Option<(char, String)>)>,
+}
+
+impl<'a> Selector<'a> {
+    /// Parse a selector, which is a tag name followed by at least one `.class`, `#id`,
+    /// `[attribute]` or `[attribute=value]`. Values may be quoted, and can't contain
+    /// brackets, or whitespace unless quoted.
+    pub fn parse(s: &'a str) -> Option<Self> {
+        let name_len = s
+            .find(|c: char| !is_name_char(c) || c == '.')
+            .unwrap_or(s.len());
+        let name = &s[..name_len];
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || name_len == s.len() {
+            return None;
+        }
+        let mut selector = Selector {
+            name,
+            attributes: Vec::new(),
+        };
+        let mut rest = &s[name_len..];
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '.' | '#' => {
+                    let len = rest
+                        .find(|c: char| !is_identifier_char(c))
+                        .unwrap_or(rest.len());
+                    let (identifier, after) = rest.split_at(len);
+                    if !is_identifier(identifier) {
+                        return None;
+                    }
+                    rest = after;
+                    let attribute = if c == '.' { "class" } else { "id" };
+                    match selector.value_mut(attribute) {
+                        Some(Some((_, classes))) if c == '.' => {
+                            classes.push(' ');
+                            classes.push_str(identifier);
+                        }
+                        // an element only has one id
+                        Some(_) => return None,
+                        None => {
+                            let value = Some(('"', identifier.to_string()));
+                            selector.attributes.push((attribute, value));
+                        }
+                    }
+                }
+                '[' => {
+                    let end = rest.find(']')?;
+                    let (name, value) = match rest[..end].split_once('=') {
+                        Some((name, value)) => (name, Some(attribute_value(value)?)),
+                        None => (&rest[..end], None),
+                    };
+                    // classes and ids are written with `.` and `#`
+                    let valid_name = !name.is_empty() && name.chars().all(is_name_char);
+                    let written_otherwise = matches!(name, "class" | "id");
+                    if !valid_name || written_otherwise || selector.value_mut(name).is_some() {
+                        return None;
+                    }
+                    selector.attributes.push((name, value));
+                    rest = &rest[end + 1..];
+                }
+                _ => return None,
+            }
+        }
+        Some(selector)
+    }
+
+    /// The start tag the selector stands for, like `<div class="card" id="main">`
+    pub fn start_tag(&self) -> String {
+        let mut tag = format!("<{}", self.name);
+        for (name, value) in &self.attributes {
+            tag.push(' ');
+            tag.push_str(name);
+            if let Some((quote, value)) = value {
+                tag.push('=');
+                tag.push(*quote);
+                tag.push_str(value);
+                tag.push(*quote);
+            }
+        }
+        tag.push('>');
+        tag
+    }
+
+    /// The element the selector stands for, without any content yet
+    pub fn element(&self) -> Element {
+        Element {
+            name: self.name.to_string(),
+            attributes: self
+                .attributes
+                .iter()
+                .map(|(name, value)| Attribute {
+                    name: name.to_string(),
+                    value: value.as_ref().map(|(_, value)| value.clone()),
+                })
+                .collect(),
+            start_tag: self.start_tag(),
+            empty_element_tag: false,
+            children: None,
+            brace_whitespace: None,
+        }
+    }
+
+    /// whether the dialect lets the element have a scope whose content is markup
+    fn fits(&self, dialect: &Dialect) -> bool {
+        !dialect.is_void(self.name) && !dialect.is_raw_text(self.name)
+    }
+
+    /// the value of the attribute called `name`, if it has been given yet
+    fn value_mut(&mut self, name: &str) -> Option<&mut Option<(char, String)>> {
+        self.attributes
+            .iter_mut()
+            .find(|(attribute, _)| *attribute == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// the value of a `[name=value]` attribute, along with the quote to write around it
+fn attribute_value(value: &str) -> Option<(char, String)> {
+    let quote = value.chars().next().filter(|&c| c == '"' || c == '\'');
+    match quote {
+        Some(quote) => {
+            let inner = value[1..].strip_suffix(quote)?;
+            (!inner.contains(quote)).then(|| (quote, inner.to_string()))
+        }
+        None => {
+            let valid = !value.is_empty()
+                && !value.contains(|c: char| c.is_ascii_whitespace() || c == '"' || c == '\'');
+            valid.then(|| ('"', value.to_string()))
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_')
+}
+
+/// Whether a class or id can be written in a selector, which like in CSS means it can't
+/// start with a digit or `-` and a digit, so that `v1.2` and `a.1` stay text
+fn is_identifier(s: &str) -> bool {
+    let unsigned = s.strip_prefix('-').unwrap_or(s);
+    !s.is_empty() && !unsigned.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Find a selector at the end of some MXML text coming just before a `{`, which is either
+/// the whole text or follows whitespace, and may itself be followed by whitespace
+/// # Params
+/// dialect - decides which elements can't be written as selectors, since they can't
+/// have a scope with markup in it
+/// # Returns
+/// the range of the selector in `text`
+pub(crate) fn find_selector(text: &str, dialect: &Dialect) -> Option<std::ops::Range<usize>> {
+    let end = trim_whitespace_end(text).len();
+    // whitespace inside the brackets of quoted attribute values is part of the selector
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in text[..end].char_indices().rev() {
+        match c {
+            ']' => depth += 1,
+            '[' => depth = depth.saturating_sub(1),
+            c if c.is_ascii_whitespace() && depth == 0 => {
+                start = i + 1;
+                break;
+            }
+            _ => {}
+        }
+    }
+    Selector::parse(&text[start..end])
+        .filter(|selector| selector.fits(dialect))
+        .map(|_| start..end)
+}
+
+/// The selector to write for an element in MXML instead of its start tag, if its only
+/// attributes are `class` and `id` and the selector stands for exactly that start tag
+pub(crate) fn selector_for<'t>(
+    start_tag: &str,
+    name: &str,
+    attributes: impl IntoIterator<Item = (&'t str, Option<&'t str>)>,
+) -> Option<String> {
+    let mut selector = name.to_string();
+    for (attribute, value) in attributes {
+        let prefix = match attribute {
+            "class" => ".",
+            "id" => "#",
+            _ => return None,
+        };
+        for identifier in value?.split(' ') {
+            selector.push_str(prefix);
+            selector.push_str(identifier);
+        }
+    }
+    let parsed = Selector::parse(&selector)?;
+    (parsed.start_tag() == start_tag).then_some(selector)
+}
+
+/// Whether a selector may be written after the node `before` without running into it,
+/// which only text not ending in whitespace would do
+fn selector_fits_after(before: Option<&Node>) -> bool {
+    match before {
+        Some(Node::Text(text)) => text.ends_with(|c: char| c.is_ascii_whitespace()),
+        _ => true,
+    }
+}
+
+/// Replace the start tags of elements in `nodes` and their descendants with selectors,
+/// wherever `selector_for` finds one, so that they are written that way as MXML
+pub(crate) fn write_selectors(nodes: &mut [Node], dialect: &Dialect) {
+    for i in 0..nodes.len() {
+        let fits = selector_fits_after(i.checked_sub(1).map(|before| &nodes[before]));
+        let Node::Element(element) = &mut nodes[i] else {
+            continue;
+        };
+        let Some(children) = &mut element.children else {
+            continue;
+        };
+        write_selectors(children, dialect);
+        if !fits || dialect.is_raw_text(&element.name) {
+            continue;
+        }
+        let attributes = element
+            .attributes
+            .iter()
+            .map(|attribute| (attribute.name.as_str(), attribute.value.as_deref()));
+        if let Some(selector) = selector_for(&element.start_tag, &element.name, attributes) {
+            element.start_tag = selector;
+        }
+    }
+}
+
+/// One-line content, written just after a start tag
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct OneLine<'a> {
+    /// the whitespace before a quoted string, which is dropped like that before a `{`
+    pub gap: &'a str,
+    pub content: &'a str,
+    /// the text after the content, which comes after the element
+    pub rest: &'a str,
+    /// whether the line ends in the text, rather than running into markup or a brace,
+    /// which can't be part of content running to the end of the line
+    pub ends_line: bool,
+}
+
+/// Find one-line content at the start of MXML text which comes just after a start tag:
+/// either a quoted string ending that line, or a colon followed by the rest of the line
+/// (without the whitespace before or after it)
+pub(crate) fn one_line(text: &str) -> Option<OneLine<'_>> {
+    if let Some(after) = text.strip_prefix(':') {
+        let line = after.trim_start_matches([' ', '\t']);
+        let line_len = line.find('\n').unwrap_or(line.len());
+        let content = trim_whitespace_end(&line[..line_len]);
+        return Some(OneLine {
+            gap: "",
+            content,
+            rest: &line[content.len()..],
+            ends_line: line_len < line.len(),
+        });
+    }
+    quoted(text).filter(|line| starts_with_line_break(line.rest))
+}
+
+/// Whether MXML text just after a start tag starts with a quoted string which isn't
+/// one-line content, since something other than whitespace follows it on its line
+pub(crate) fn quoted_mid_line(text: &str) -> bool {
+    quoted(text).is_some_and(|line| !starts_with_line_break(line.rest))
+}
+
+/// the quoted string on the first line of `text`, after any spaces or tabs
+fn quoted(text: &str) -> Option<OneLine<'_>> {
+    let quoted = text.trim_start_matches([' ', '\t']);
+    let inner = quoted.strip_prefix('"')?;
+    let end = inner.find(['"', '\n'])?;
+    (inner.as_bytes()[end] == b'"').then(|| OneLine {
+        gap: &text[..text.len() - quoted.len()],
+        content: &inner[..end],
+        rest: &inner[end + 1..],
+        ends_line: true,
+    })
+}
+
+/// Whether `text` starts with a line break, after any other whitespace
+pub(crate) fn starts_with_line_break(text: &str) -> bool {
+    text.trim_start_matches(|c: char| c.is_ascii_whitespace() && c != '\n')
+        .starts_with('\n')
+}
+
+/// Whether the text content of an element, as written in MXML, can be written as a
+/// quoted string after its start tag
+pub(crate) fn fits_one_line(text: &str) -> bool {
+    !text.is_empty()
+        && !text.contains(['"', '\n', '\r', '<'])
+        && text.chars().count() <= ONE_LINE_MAX_CHARS
+}
+
+/// `start_tag` followed by `content` as a quoted string, like `<li> "text"`
+pub(crate) fn one_line_form(start_tag: &str, content: &str) -> String {
+    format!("{start_tag} \"{content}\"")
+}
+
+/// Write elements in `nodes` and their descendants whose only content is text which
+/// `fits_one_line` as one-line content, by making it part of their start tag. Only
+/// elements followed by a line break are, since the quoted string has to end its line.
+pub(crate) fn write_one_line(nodes: &mut [Node]) {
+    for i in 0..nodes.len() {
+        let ends_line =
+            matches!(nodes.get(i + 1), Some(Node::Text(after)) if starts_with_line_break(after));
+        let Node::Element(element) = &mut nodes[i] else {
+            continue;
+        };
+        match element.children.as_deref_mut() {
+            Some([Node::Text(text)]) if ends_line && fits_one_line(text) => {
+                element.start_tag = one_line_form(&element.start_tag, text);
+                element.children = None;
+            }
+            Some(children) => write_one_line(children),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_tag(selector: &str) -> Option<String> {
+        Selector::parse(selector).map(|selector| selector.start_tag())
+    }
+
+    #[test]
+    fn selectors_expand_to_start_tags() {
+        assert_eq!(
+            start_tag("div.card.wide#main").unwrap(),
+            "<div class=\"card wide\" id=\"main\">"
+        );
+        assert_eq!(
+            start_tag("a#top.x[href=/][title='a b'][hidden].y").unwrap(),
+            "<a id=\"top\" class=\"x y\" href=\"/\" title='a b' hidden>"
+        );
+        for invalid in [
+            "div",
+            ".card",
+            "div.",
+            "div#a#b",
+            "div[class=x]",
+            "div[a=b c]",
+            "div.a{",
+            "e.g.",
+            "1.5",
+            "v1.2",
+            "a.1",
+            "p#2x",
+            "p.-1",
+        ] {
+            assert_eq!(start_tag(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn selectors_found_before_braces() {
+        let html = Dialect::html5();
+        assert_eq!(find_selector("text div.a \n", &html), Some(5..10));
+        assert_eq!(find_selector("li[title=\"a b\"]", &html), Some(0..15));
+        assert_eq!(find_selector("x.y.z]", &html), None);
+        assert_eq!(find_selector("text", &html), None);
+        // elements which can't have markup in a scope
+        assert_eq!(find_selector("br.x", &html), None);
+        assert_eq!(find_selector("style.x", &html), None);
+        assert_eq!(find_selector("style.x", &Dialect::xml()), Some(0..7));
+    }
+
+    #[test]
+    fn selectors_only_for_class_and_id() {
+        let selector = |start_tag, attributes: &[(&'static str, Option<&'static str>)]| {
+            selector_for(start_tag, "p", attributes.iter().copied())
+        };
+        assert_eq!(
+            selector(
+                "<p id=\"x\" class=\"a b\">",
+                &[("id", Some("x")), ("class", Some("a b"))]
+            ),
+            Some("p#x.a.b".into())
+        );
+        // anything the selector wouldn't give back exactly is left as it is
+        assert_eq!(selector("<p class='a'>", &[("class", Some("a"))]), None);
+        assert_eq!(
+            selector("<p class=\"a  b\">", &[("class", Some("a  b"))]),
+            None
+        );
+        assert_eq!(selector("<p title=\"a\">", &[("title", Some("a"))]), None);
+        assert_eq!(selector("<p>", &[]), None);
+    }
+
+    #[test]
+    fn one_line_content() {
+        let line = |content, gap, rest, ends_line| {
+            Some(OneLine {
+                gap,
+                content,
+                rest,
+                ends_line,
+            })
+        };
+        assert_eq!(one_line(" \"a: b\" \nc"), line("a: b", " ", " \nc", true));
+        assert_eq!(
+            one_line(":  a \"b\" \r\nc"),
+            line("a \"b\"", "", " \r\nc", true)
+        );
+        assert_eq!(one_line("\"\"\r\n"), line("", "", "\r\n", true));
+        // markup or a brace may come next on the line, which can't be part of the content
+        assert_eq!(one_line(":"), line("", "", "", false));
+        assert_eq!(one_line(": a "), line("a", "", " ", false));
+        // a quoted string has to end on the same line
+        assert_eq!(one_line(" \"a\nb\""), None);
+        assert_eq!(one_line(" a: b"), None);
+        assert_eq!(one_line("\n\"a\""), None);
+        // and end its line, so that prose starting with a quote stays text
+        for text in ["\"Hi\" she said\n", " \"a\" ", "\"a\""] {
+            assert_eq!(one_line(text), None, "{text}");
+            assert!(quoted_mid_line(text), "{text}");
+        }
+        assert!(!quoted_mid_line(" \"a\"\n"));
+    }
+}
@@ -0,0 +1,1379 @@
+//! Converting markup as it is read, without building a document tree, which is how every
+//! conversion function converts
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use crate::document::{braces_balance, lowercase_tag_name, trim_whitespace_end, MAX_NESTING_DEPTH};
+use crate::error::{Error, ErrorKind};
+use crate::format::MxmlStyle;
+use crate::shorthand::{self, Selector, Shorthands};
+use crate::tokenizer::{RawTextState, Syntax, Tag, Token, TokenKind, Tokenizer};
+use crate::{replace_bracket_escapes, replace_brackets, Dialect, Direction, Options};
+
+/// how much of a stream to read before tokenizing it
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Convert markup read from `reader`, writing the result to `writer` as it goes. Only the
+/// scopes which are still open and the token being read are held in memory, and every
+/// token is looked at once, so conversion takes time linear in the size of the source.
+/// The output is the same as `convert_with_options` gives, or would give if it
+/// didn't stop at errors, since it converts the same way.
+/// # Params
+/// reader - where to read the source from, which must be UTF-8
+/// writer - where to write the converted text
+/// direction - which way to convert
+/// options - how to convert
+/// # Returns
+/// Every error found in the source, which are displayed without a snippet of the source
+/// since it's gone by the time they are returned, or an error if reading or writing failed
+pub fn convert_stream(
+    reader: impl BufRead,
+    writer: impl Write,
+    direction: Direction,
+    options: &Options,
+) -> io::Result<Vec<Error>> {
+    convert_in_chunks(reader, writer, direction, options, CHUNK_SIZE)
+}
+
+/// Convert `source`, writing the result to `writer` as it goes rather than building
+/// a `String`. Like `convert_stream`, the output is written even if there are errors.
+/// # Params
+/// source - the source text
+/// writer - where to write the converted text
+/// direction - which way to convert
+/// options - how to convert
+/// # Returns
+/// Every error found in the source, or an error if writing failed
+pub fn convert_to_writer(
+    source: &str,
+    writer: impl Write,
+    direction: Direction,
+    options: &Options,
+) -> io::Result<Vec<Error>> {
+    let errors = convert(
+        Input::<io::Empty>::Text(source),
+        BufWriter::new(writer),
+        direction,
+        options,
+    )?;
+    Ok(with_snippets(errors, source))
+}
+
+/// Convert `source`, writing the result to `out` as it goes rather than building a
+/// `String`, e.g. straight into a template being rendered. Like `convert_stream`, the
+/// output is written even if there are errors.
+/// # Params
+/// source - the source text
+/// out - where to write the converted text
+/// direction - which way to convert
+/// options - how to convert
+/// # Returns
+/// Every error found in the source, or an error if writing failed
+pub fn convert_to_fmt(
+    source: &str,
+    out: impl fmt::Write,
+    direction: Direction,
+    options: &Options,
+) -> Result<Vec<Error>, fmt::Error> {
+    let errors = convert(
+        Input::<io::Empty>::Text(source),
+        FmtSink(out),
+        direction,
+        options,
+    )
+    .map_err(|_| fmt::Error)?;
+    Ok(with_snippets(errors, source))
+}
+
+/// Convert `source` into a `String`, which is what `convert_with_options` and the other
+/// conversion functions taking a whole source use
+/// # Returns
+/// The converted text, written even if there are errors, and every error found in the source
+pub(crate) fn convert_to_string(
+    source: &str,
+    direction: Direction,
+    options: &Options,
+) -> (String, Vec<Error>) {
+    let mut converted = String::with_capacity(source.len());
+    let errors = match convert(
+        Input::<io::Empty>::Text(source),
+        &mut converted,
+        direction,
+        options,
+    ) {
+        Ok(errors) => errors,
+        Err(_) => unreachable!("writing to a String never fails"),
+    };
+    (converted, with_snippets(errors, source))
+}
+
+/// Convert like `convert_stream`, tokenizing the source `chunk_size` bytes at a time
+fn convert_in_chunks(
+    reader: impl BufRead,
+    writer: impl Write,
+    direction: Direction,
+    options: &Options,
+    chunk_size: usize,
+) -> io::Result<Vec<Error>> {
+    let input = Input::Reader { reader, chunk_size };
+    convert(input, BufWriter::new(writer), direction, options)
+}
+
+/// give errors found while converting `source` snippets of it
+fn with_snippets(errors: Vec<Error>, source: &str) -> Vec<Error> {
+    errors
+        .into_iter()
+        .map(|error| Error::new(error.kind().clone(), source, error.span()))
+        .collect()
+}
+
+/// Where the source comes from
+enum Input<'s, R> {
+    /// read bit by bit, tokenizing at least `chunk_size` bytes at a time
+    Reader { reader: R, chunk_size: usize },
+    /// all at hand already
+    Text(&'s str),
+}
+
+/// Somewhere to write converted text
+trait Sink {
+    fn write_str(&mut self, s: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl<W: Write> Sink for BufWriter<W> {
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.write_all(s.as_bytes())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Write::flush(self)
+    }
+}
+
+impl Sink for &mut String {
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.push_str(s);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A `fmt::Write` as a `Sink`. Its errors don't say anything, so neither do ours.
+struct FmtSink<W>(W);
+
+impl<W: fmt::Write> Sink for FmtSink<W> {
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.0
+            .write_str(s)
+            .map_err(|_| io::Error::other("formatter error"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn convert<R: BufRead>(
+    input: Input<R>,
+    sink: impl Sink,
+    direction: Direction,
+    options: &Options,
+) -> io::Result<Vec<Error>> {
+    let dialect = &options.dialect;
+    match direction {
+        Direction::MxmlToXml => {
+            let converter = MxmlToXml {
+                dialect,
+                keep_brace_whitespace: options.keep_brace_whitespace,
+                lowercase_tag_names: options.lowercase_tag_names,
+                remove_annotations: options.remove_annotations,
+                shorthands: &options.shorthands,
+                scopes: Vec::new(),
+                tail: Tail::Other,
+                unfinished_line: None,
+            };
+            Run::new(converter, Syntax::Mxml, dialect, sink).convert(input)
+        }
+        Direction::XmlToMxml => {
+            let converter = XmlToMxml {
+                dialect,
+                style: &options.style,
+                lowercase_tag_names: options.lowercase_tag_names,
+                shorthands: &options.shorthands,
+                open: Vec::new(),
+                held: String::new(),
+                after_word: false,
+            };
+            Run::new(converter, Syntax::Xml, dialect, sink).convert(input)
+        }
+    }
+}
+
+/// Converts one token at a time, keeping only what it needs to know about the tokens before
+trait Convert {
+    /// Convert `token`, which starts at `at` in the source
+    /// # Returns
+    /// whether to carry on, which is `false` once the source is nested too deeply
+    fn token(
+        &mut self,
+        token: &Token,
+        at: &Position,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+    ) -> io::Result<bool>;
+
+    /// Close everything still open at the end of the source
+    /// # Params
+    /// stopped - whether conversion stopped early, in which case nothing is reported as unclosed
+    fn finish(
+        self,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+        stopped: bool,
+    ) -> io::Result<()>;
+}
+
+/// A conversion in progress
+struct Run<'a, C, S> {
+    converter: C,
+    syntax: Syntax,
+    dialect: &'a Dialect,
+    out: Output<S>,
+    errors: Vec<Error>,
+    /// where the next token starts
+    at: Position,
+    raw_text_state: Option<RawTextState>,
+    /// whether the source turned out to be nested too deeply to carry on
+    stopped: bool,
+}
+
+impl<'a, C: Convert, S: Sink> Run<'a, C, S> {
+    fn new(converter: C, syntax: Syntax, dialect: &'a Dialect, sink: S) -> Self {
+        Run {
+            converter,
+            syntax,
+            dialect,
+            out: Output {
+                sink,
+                newlines: 0,
+                line_blank: true,
+                undecided: None,
+            },
+            errors: Vec::new(),
+            at: Position {
+                offset: 0,
+                line: 1,
+                column: 1,
+            },
+            raw_text_state: None,
+            stopped: false,
+        }
+    }
+
+    /// Convert the whole of `input`
+    /// # Returns
+    /// every error found in the source
+    fn convert(mut self, input: Input<impl BufRead>) -> io::Result<Vec<Error>> {
+        match input {
+            Input::Text(text) => {
+                self.tokens(text, true)?;
+            }
+            Input::Reader { reader, chunk_size } => self.read(reader, chunk_size)?,
+        }
+        self.converter
+            .finish(&mut self.out, &mut self.errors, self.stopped)?;
+        self.out.sink.flush()?;
+        Ok(self.errors)
+    }
+
+    /// Convert the source read from `reader`, `chunk_size` bytes or more at a time
+    fn read(&mut self, mut reader: impl BufRead, chunk_size: usize) -> io::Result<()> {
+        // the source which has been read but not yet tokenized
+        let mut input = Vec::new();
+        let mut end_of_input = false;
+        while !end_of_input && !self.stopped {
+            // a token longer than what's left over needs more input than it took to find that
+            // out, so that no part of the source gets tokenized more than a few times
+            let wanted = chunk_size.max(2 * input.len());
+            end_of_input = read_at_least(&mut reader, &mut input, wanted)?;
+            let text = utf8_prefix(&input, end_of_input)?;
+            let tokenized = self.tokens(text, end_of_input)?;
+            input.drain(..tokenized);
+        }
+        Ok(())
+    }
+
+    /// Convert the tokens in `text`, which carries on from where the last text left off
+    /// # Params
+    /// complete - whether `text` runs to the end of the source
+    /// # Returns
+    /// how much of `text` was converted, which is all of it if it's complete, and
+    /// otherwise stops short of anything which more of the source could change
+    fn tokens(&mut self, text: &str, complete: bool) -> io::Result<usize> {
+        let mut tokenizer = Tokenizer::new(text, self.syntax)
+            .with_dialect(self.dialect)
+            .resume(self.raw_text_state.take());
+        if !complete {
+            tokenizer = tokenizer.incomplete();
+        }
+        for token in &mut tokenizer {
+            let carry_on =
+                self.converter
+                    .token(&token, &self.at, &mut self.out, &mut self.errors)?;
+            if !carry_on {
+                self.stopped = true;
+                break;
+            }
+            self.at.advance(token.raw);
+        }
+        self.raw_text_state = tokenizer.raw_text_state();
+        Ok(tokenizer.position())
+    }
+}
+
+/// Read from `reader` onto the end of `input` until it holds at least `len` bytes
+/// # Returns
+/// whether the end of the input was reached
+fn read_at_least(reader: &mut impl BufRead, input: &mut Vec<u8>, len: usize) -> io::Result<bool> {
+    while input.len() < len {
+        let chunk = match reader.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        if chunk.is_empty() {
+            return Ok(true);
+        }
+        input.extend_from_slice(chunk);
+        let read = chunk.len();
+        reader.consume(read);
+    }
+    Ok(false)
+}
+
+/// The longest prefix of `input` which is valid UTF-8. Before the end of the input, a
+/// character may be cut off by the end of what has been read so far.
+fn utf8_prefix(input: &[u8], end_of_input: bool) -> io::Result<&str> {
+    match std::str::from_utf8(input) {
+        Ok(text) => Ok(text),
+        Err(error) if error.error_len().is_none() && !end_of_input => {
+            Ok(std::str::from_utf8(&input[..error.valid_up_to()]).unwrap_or_default())
+        }
+        Err(error) => Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+    }
+}
+
+/// Where a token starts in the source
+#[derive(Debug, Clone)]
+struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    /// move past `raw`
+    fn advance(&mut self, raw: &str) {
+        self.offset += raw.len();
+        match raw.rfind('\n') {
+            Some(i) => {
+                self.line += count_newlines(raw);
+                self.column = count_chars(&raw[i + 1..]) + 1;
+            }
+            None => self.column += count_chars(raw),
+        }
+    }
+
+    /// an error about the `len` bytes starting here
+    fn error(&self, kind: ErrorKind, len: usize) -> Error {
+        Error::located(kind, self.offset..self.offset + len, self.line, self.column)
+    }
+}
+
+/// The sink being converted into, keeping track of what has been written so that
+/// scopes can be laid out like `Document::to_mxml_styled` does
+struct Output<S> {
+    sink: S,
+    /// how many line breaks have been written
+    newlines: usize,
+    /// whether the line being written is only whitespace so far
+    line_blank: bool,
+    /// an element with one-line content which hasn't been written yet, since it's only
+    /// written that way if a line break follows it
+    undecided: Option<Undecided>,
+}
+
+/// The two ways of writing an element with one-line content
+struct Undecided {
+    /// with the content quoted after the start tag, like `<li> "text"`
+    one_line: String,
+    /// in a scope, like `<li> {text}`
+    scoped: String,
+}
+
+impl<S: Sink> Output<S> {
+    /// write anything but text, which means an undecided element before it is scoped
+    fn write(&mut self, s: &str) -> io::Result<()> {
+        if !s.is_empty() {
+            self.decide(false)?;
+        }
+        self.write_raw(s)
+    }
+
+    /// write text, which decides how an undecided element before it is written
+    fn write_text(&mut self, s: &str) -> io::Result<()> {
+        if !s.is_empty() {
+            self.decide(shorthand::starts_with_line_break(s))?;
+        }
+        self.write_raw(s)
+    }
+
+    /// hold back an element with one-line content until it's known what follows it
+    fn defer(&mut self, undecided: Undecided) -> io::Result<()> {
+        self.decide(false)?;
+        self.undecided = Some(undecided);
+        Ok(())
+    }
+
+    /// write the undecided element, if there is one
+    /// # Params
+    /// line_break_follows - whether it's followed by a line break, so its content can be
+    /// written on one line
+    fn decide(&mut self, line_break_follows: bool) -> io::Result<()> {
+        match self.undecided.take() {
+            Some(undecided) if line_break_follows => self.write_raw(&undecided.one_line),
+            Some(undecided) => self.write_raw(&undecided.scoped),
+            None => Ok(()),
+        }
+    }
+
+    fn write_raw(&mut self, s: &str) -> io::Result<()> {
+        match s.rfind('\n') {
+            Some(i) => {
+                self.newlines += count_newlines(s);
+                self.line_blank = s[i + 1..].trim().is_empty();
+            }
+            None => self.line_blank &= s.trim().is_empty(),
+        }
+        self.sink.write_str(s)
+    }
+}
+
+// these count bytes, which is a lot quicker than going through `str::matches` and `str::chars`
+
+fn count_newlines(s: &str) -> usize {
+    s.bytes().filter(|&b| b == b'\n').count()
+}
+
+fn count_chars(s: &str) -> usize {
+    // every character has exactly one byte which isn't a continuation byte
+    s.bytes().filter(|&b| b & 0xC0 != 0x80).count()
+}
+
+/// Converts MXML to XML, replacing each element's scope with an end tag
+struct MxmlToXml<'a> {
+    dialect: &'a Dialect,
+    keep_brace_whitespace: bool,
+    lowercase_tag_names: bool,
+    remove_annotations: bool,
+    shorthands: &'a Shorthands,
+    scopes: Vec<Scope>,
+    tail: Tail,
+    /// the name of an element with one-line content like `<b>: text` whose line hasn't
+    /// ended yet, so that anything else on it is an error
+    unfinished_line: Option<String>,
+}
+
+/// A scope which has been opened but not yet closed, along with where its `{` is
+enum Scope {
+    /// scope belonging to the element with this name
+    Element(String, Position),
+    /// scope without a tag before it, whose braces are kept as literal text
+    Literal(Position),
+}
+
+/// What came just before the current token, where that decides what the token means.
+/// Anything held back here is written once it turns out not to matter.
+enum Tail {
+    Other,
+    /// a start tag, along with the whitespace after it, which is held back until
+    /// it's known whether a scope belonging to the element follows
+    StartTag {
+        name: String,
+        /// whether the element may have a scope, which void elements can't
+        scopeable: bool,
+        gap: String,
+    },
+    /// a selector which stands for a start tag if a scope follows, along with the
+    /// whitespace after it, both held back until it's known whether one does
+    Selector {
+        selector: String,
+        gap: String,
+    },
+    /// the content of the raw text element with this name, written without a scope
+    RawText(String),
+    /// the end of the element with this name, along with whether a space after it has
+    /// been held back, in case a `<!-- /name -->` annotation follows which should be removed
+    Closed {
+        name: String,
+        space: bool,
+    },
+}
+
+impl MxmlToXml<'_> {
+    /// write out whatever the tail is holding back, since it turned out not to matter
+    fn flush(&mut self, out: &mut Output<impl Sink>) -> io::Result<()> {
+        match std::mem::replace(&mut self.tail, Tail::Other) {
+            Tail::StartTag { gap, .. } => out.write(&gap),
+            Tail::Closed { space: true, .. } => out.write(" "),
+            Tail::Selector { selector, gap } => {
+                write_unescaped(out, &selector)?;
+                out.write(&gap)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Convert for MxmlToXml<'_> {
+    fn token(
+        &mut self,
+        token: &Token,
+        at: &Position,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+    ) -> io::Result<bool> {
+        if let Some(name) = self.unfinished_line.take() {
+            let kind = ErrorKind::OneLineInterrupted(name);
+            errors.push(at.error(kind, token.raw.len()));
+        }
+        if token.kind == TokenKind::ScopeOpen && self.scopes.len() == MAX_NESTING_DEPTH {
+            errors.push(at.error(ErrorKind::NestingTooDeep, token.raw.len()));
+            self.flush(out)?;
+            return Ok(false);
+        }
+
+        // tokens which mean something different after a start tag or the end of an element
+        match (&token.kind, &mut self.tail) {
+            (TokenKind::Text, Tail::StartTag { gap, .. }) if token.raw.trim().is_empty() => {
+                gap.push_str(token.raw);
+                return Ok(true);
+            }
+            (TokenKind::Text, Tail::Closed { space, .. })
+                if self.remove_annotations && token.raw == " " && !*space =>
+            {
+                *space = true;
+                return Ok(true);
+            }
+            (TokenKind::Comment, Tail::Closed { name, .. })
+                if self.remove_annotations && token.raw == format!("<!-- /{name} -->") =>
+            {
+                self.tail = Tail::Other;
+                return Ok(true);
+            }
+            (
+                TokenKind::ScopeOpen,
+                Tail::StartTag {
+                    name,
+                    scopeable: true,
+                    gap,
+                },
+            ) => {
+                if self.keep_brace_whitespace {
+                    out.write(gap)?;
+                }
+                let name = std::mem::take(name);
+                self.scopes.push(Scope::Element(name, at.clone()));
+                self.tail = Tail::Other;
+                return Ok(true);
+            }
+            (
+                TokenKind::Text,
+                Tail::StartTag {
+                    name,
+                    scopeable: true,
+                    gap,
+                },
+            ) if self.shorthands.one_line && gap.is_empty() => {
+                if let Some(line) = shorthand::one_line(token.raw) {
+                    if self.keep_brace_whitespace {
+                        out.write(line.gap)?;
+                    }
+                    write_unescaped(out, line.content)?;
+                    let name = std::mem::take(name);
+                    write_end_tag(out, &name, self.lowercase_tag_names)?;
+                    if !line.ends_line {
+                        self.unfinished_line = Some(name.clone());
+                    }
+                    // the rest of the text comes after the element
+                    self.tail = Tail::Closed {
+                        name,
+                        space: line.rest == " ",
+                    };
+                    if !matches!(line.rest, "" | " ") {
+                        self.tail = Tail::Other;
+                        self.text(out, line.rest)?;
+                    }
+                    return Ok(true);
+                }
+            }
+            (TokenKind::ScopeOpen, Tail::Selector { selector, gap }) => {
+                if let Some(selector) = Selector::parse(selector) {
+                    let start_tag = selector.start_tag();
+                    write_start_tag(out, &start_tag, selector.name, self.lowercase_tag_names)?;
+                    if self.keep_brace_whitespace {
+                        out.write(gap)?;
+                    }
+                    let name = selector.name.to_string();
+                    self.scopes.push(Scope::Element(name, at.clone()));
+                }
+                self.tail = Tail::Other;
+                return Ok(true);
+            }
+            (TokenKind::RawText, Tail::StartTag { name, gap, .. })
+                if gap.is_empty() && self.dialect.is_raw_text(name) =>
+            {
+                out.write(token.raw)?;
+                self.tail = Tail::RawText(std::mem::take(name));
+                return Ok(true);
+            }
+            (TokenKind::EndTag(tag), Tail::StartTag { name, gap, .. })
+                if gap.is_empty()
+                    && self.dialect.names_match(name, tag.name)
+                    && self.dialect.is_raw_text(tag.name) =>
+            {
+                return self.close_raw_text_element(out);
+            }
+            (TokenKind::EndTag(tag), Tail::RawText(name))
+                if self.dialect.names_match(name, tag.name) =>
+            {
+                return self.close_raw_text_element(out);
+            }
+            _ => {}
+        }
+
+        self.flush(out)?;
+        match &token.kind {
+            TokenKind::ScopeOpen => {
+                out.write(token.raw)?;
+                self.scopes.push(Scope::Literal(at.clone()));
+            }
+            TokenKind::ScopeClose => match self.scopes.pop() {
+                Some(Scope::Element(name, _)) => {
+                    write_end_tag(out, &name, self.lowercase_tag_names)?;
+                    self.tail = Tail::Closed { name, space: false };
+                }
+                Some(Scope::Literal(_)) => out.write(token.raw)?,
+                None => {
+                    errors.push(at.error(ErrorKind::UnmatchedScopeClose, token.raw.len()));
+                    out.write(token.raw)?;
+                }
+            },
+            TokenKind::StartTag(tag) => {
+                write_start_tag(out, token.raw, tag.name, self.lowercase_tag_names)?;
+                self.tail = Tail::StartTag {
+                    name: tag.name.to_string(),
+                    scopeable: !self.dialect.is_void(tag.name),
+                    gap: String::new(),
+                };
+            }
+            TokenKind::EmptyElementTag(tag) => {
+                write_start_tag(out, token.raw, tag.name, self.lowercase_tag_names)?
+            }
+            TokenKind::Text => self.text(out, token.raw)?,
+            // end tags other than those of raw text elements have no meaning in MXML,
+            // so they are text like any other
+            TokenKind::EndTag(_) => write_unescaped(out, token.raw)?,
+            _ => out.write(token.raw)?,
+        }
+        Ok(true)
+    }
+
+    fn finish(
+        mut self,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+        stopped: bool,
+    ) -> io::Result<()> {
+        self.flush(out)?;
+        if !stopped {
+            for unclosed in &self.scopes {
+                let (Scope::Element(_, at) | Scope::Literal(at)) = unclosed;
+                errors.push(at.error(ErrorKind::UnclosedScope, 1));
+            }
+        }
+        while let Some(scope) = self.scopes.pop() {
+            if let Scope::Element(name, _) = scope {
+                write_end_tag(out, &name, self.lowercase_tag_names)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MxmlToXml<'_> {
+    /// write text, holding back a selector it ends with in case a scope follows
+    fn text(&mut self, out: &mut Output<impl Sink>, raw: &str) -> io::Result<()> {
+        let selector = match self.shorthands.selectors {
+            true => shorthand::find_selector(raw, self.dialect),
+            false => None,
+        };
+        match selector {
+            Some(range) => {
+                write_unescaped(out, &raw[..range.start])?;
+                self.tail = Tail::Selector {
+                    selector: raw[range.clone()].to_string(),
+                    gap: raw[range.end..].to_string(),
+                };
+                Ok(())
+            }
+            None => write_unescaped(out, raw),
+        }
+    }
+
+    /// write the end tag of the raw text element in the tail, which was written without a scope
+    fn close_raw_text_element(&mut self, out: &mut Output<impl Sink>) -> io::Result<bool> {
+        let (Tail::StartTag { name, .. } | Tail::RawText(name)) =
+            std::mem::replace(&mut self.tail, Tail::Other)
+        else {
+            return Ok(true);
+        };
+        write_end_tag(out, &name, self.lowercase_tag_names)?;
+        self.tail = Tail::Closed { name, space: false };
+        Ok(true)
+    }
+}
+
+/// Converts XML to MXML, replacing each element's end tag with a scope
+struct XmlToMxml<'a> {
+    dialect: &'a Dialect,
+    style: &'a MxmlStyle,
+    lowercase_tag_names: bool,
+    shorthands: &'a Shorthands,
+    open: Vec<OpenElement>,
+    /// whitespace ending the text of the innermost element, held back since it goes after
+    /// the element's scope if the element's end tag turns out to be left out
+    held: String,
+    /// whether the last thing written was text not ending in whitespace, which a
+    /// selector written next would run into
+    after_word: bool,
+}
+
+/// An element whose end tag hasn't been found yet, along with where its start tag is
+struct OpenElement {
+    name: String,
+    at: Position,
+    /// byte length of the start tag
+    len: usize,
+    content: Content,
+}
+
+/// How the content of an open element is being written
+enum Content {
+    /// not started yet, since the element is a raw text element and whether its raw text
+    /// fits in a scope isn't known until it's been read
+    Undecided,
+    /// in a scope, which was opened when this many line breaks had been written
+    Scope { newlines: usize },
+    /// followed by an end tag, since the element's raw text has braces which would end
+    /// a scope early
+    EndTag,
+    /// not written yet, not even the start tag, since the element may turn out to only
+    /// have a line of text in it, to be written as one-line content
+    Pending {
+        /// the start tag, to write the one-line content after
+        start_tag: String,
+        /// what to write for the start tag if there turns out to be more content
+        head: String,
+        /// the text read so far
+        text: Option<String>,
+    },
+}
+
+impl XmlToMxml<'_> {
+    /// open the scope of the innermost open element
+    fn open_scope(&mut self, out: &mut Output<impl Sink>) -> io::Result<()> {
+        let depth = self.open.len().saturating_sub(1);
+        let mut open = String::new();
+        self.style.write_open(&mut open, depth);
+        out.write(&open)?;
+        if let Some(element) = self.open.last_mut() {
+            element.content = Content::Scope {
+                newlines: out.newlines,
+            };
+        }
+        Ok(())
+    }
+
+    /// write the whitespace held back, now that it's known where it goes
+    fn write_held(&mut self, out: &mut Output<impl Sink>) -> io::Result<()> {
+        out.write_text(&self.held)?;
+        self.held.clear();
+        Ok(())
+    }
+
+    /// the selector to write instead of `start_tag`, if it can be written as one, like
+    /// `shorthand::write_selectors` decides
+    /// # Params
+    /// start_tag - the start tag of `tag`, as it would be written
+    /// after_word - whether the last thing written was text not ending in whitespace
+    fn selector(&self, start_tag: &str, tag: &Tag, after_word: bool) -> Option<String> {
+        let name = tag_name(tag.name, self.lowercase_tag_names);
+        let raw_text = self.dialect.is_raw_text(tag.name) || self.dialect.is_raw_text(&name);
+        if !self.shorthands.selectors || after_word || raw_text {
+            return None;
+        }
+        let attributes = tag.attributes.iter().map(|a| (a.name, a.value));
+        shorthand::selector_for(start_tag, &name, attributes)
+    }
+
+    /// write text in the innermost element, holding back the whitespace it ends with if
+    /// the element's end tag is optional
+    fn text(&mut self, out: &mut Output<impl Sink>, raw: &str) -> io::Result<()> {
+        let scoped = matches!(
+            self.open.last(),
+            Some(OpenElement {
+                content: Content::Scope { .. },
+                ..
+            })
+        );
+        if scoped && self.innermost_end_tag_optional() {
+            let text = trim_whitespace_end(raw);
+            write_escaped(out, text)?;
+            self.held.push_str(&raw[text.len()..]);
+            self.after_word = self.held.is_empty();
+        } else {
+            write_escaped(out, raw)?;
+            self.after_word = trim_whitespace_end(raw).len() == raw.len();
+        }
+        Ok(())
+    }
+
+    /// write the start tag and any text of the innermost element if they are still held
+    /// back, since the element has turned out to have more than a line of text in it
+    /// # Returns
+    /// whether there was anything held back
+    fn write_pending(&mut self, out: &mut Output<impl Sink>) -> io::Result<bool> {
+        let Some(OpenElement {
+            content: Content::Pending { head, text, .. },
+            ..
+        }) = self.open.last_mut()
+        else {
+            return Ok(false);
+        };
+        let text = text.take();
+        out.write(head)?;
+        self.open_scope(out)?;
+        if let Some(text) = text {
+            self.text(out, &text)?;
+        }
+        Ok(true)
+    }
+
+    /// write the innermost element with one-line content, if it was held back and its
+    /// content fits on one line, or in a scope if no line break turns out to follow it
+    /// # Params
+    /// implied - whether the element's end tag was left out, in which case the whitespace
+    /// its text ends with goes after it
+    /// # Returns
+    /// whether the element was written, or held back to be once it is known what follows it
+    fn write_one_line(&mut self, out: &mut Output<impl Sink>, implied: bool) -> io::Result<bool> {
+        let Some(OpenElement {
+            name,
+            content:
+                Content::Pending {
+                    start_tag,
+                    head,
+                    text,
+                },
+            ..
+        }) = self.open.last()
+        else {
+            return Ok(false);
+        };
+        let text = text.as_deref().unwrap_or_default();
+        let content = if implied {
+            trim_whitespace_end(text)
+        } else {
+            text
+        };
+        let escaped = replace_brackets(content);
+        if !shorthand::fits_one_line(&escaped) {
+            return Ok(false);
+        }
+        let mut scoped = head.clone();
+        self.style.write_open(&mut scoped, self.open.len() - 1);
+        scoped.push_str(&escaped);
+        let name = tag_name(name, self.lowercase_tag_names);
+        self.style.write_close(&mut scoped, &name, 0);
+        out.defer(Undecided {
+            one_line: shorthand::one_line_form(start_tag, &escaped),
+            scoped,
+        })?;
+        self.held.push_str(&text[content.len()..]);
+        self.open.pop();
+        Ok(true)
+    }
+
+    /// whether the innermost open element may be closed without an end tag
+    fn innermost_end_tag_optional(&self) -> bool {
+        self.open
+            .last()
+            .is_some_and(|innermost| self.dialect.end_tag_optional(&innermost.name))
+    }
+
+    /// close the innermost open element, like `document::write_scope` does
+    /// # Params
+    /// implied - whether the element's end tag was left out, in which case any whitespace
+    /// held back goes after its scope
+    fn close_innermost(&mut self, out: &mut Output<impl Sink>, implied: bool) -> io::Result<()> {
+        if self.write_one_line(out, implied)? {
+            return Ok(());
+        }
+        self.write_pending(out)?;
+        if !implied {
+            self.write_held(out)?;
+        }
+        if matches!(
+            self.open.last(),
+            Some(OpenElement {
+                content: Content::Undecided,
+                ..
+            })
+        ) {
+            self.open_scope(out)?;
+        }
+        let Some(element) = self.open.pop() else {
+            return Ok(());
+        };
+        let depth = self.open.len();
+        match element.content {
+            Content::Scope { newlines } => {
+                // an element with one-line content just before the `}` is scoped
+                out.decide(false)?;
+                let lines = out.newlines - newlines;
+                let mut close = String::new();
+                if self.style.close_on_own_line && lines > 0 && !out.line_blank {
+                    close.push('\n');
+                    self.style.indent.write(&mut close, depth);
+                }
+                let name = tag_name(&element.name, self.lowercase_tag_names);
+                self.style.write_close(&mut close, &name, lines);
+                out.write(&close)
+            }
+            Content::EndTag | Content::Undecided | Content::Pending { .. } => {
+                write_end_tag(out, &element.name, self.lowercase_tag_names)
+            }
+        }
+    }
+}
+
+impl Convert for XmlToMxml<'_> {
+    fn token(
+        &mut self,
+        token: &Token,
+        at: &Position,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+    ) -> io::Result<bool> {
+        if let Some(element) = self.open.last_mut() {
+            if let Content::Pending {
+                text: text @ None, ..
+            } = &mut element.content
+            {
+                if token.kind == TokenKind::Text {
+                    *text = Some(token.raw.to_string());
+                    return Ok(true);
+                }
+            }
+            if let Content::Undecided = element.content {
+                if token.kind == TokenKind::RawText {
+                    if braces_balance(token.raw) {
+                        self.open_scope(out)?;
+                    } else {
+                        element.content = Content::EndTag;
+                    }
+                    out.write(token.raw)?;
+                    return Ok(true);
+                }
+                self.open_scope(out)?;
+            }
+        }
+
+        let mut after_word = std::mem::replace(&mut self.after_word, false);
+        if let TokenKind::StartTag(tag) | TokenKind::EmptyElementTag(tag) = &token.kind {
+            let names = self.open.iter().map(|open| open.name.as_str());
+            for _ in 0..self.dialect.implied_ends(names, tag.name) {
+                self.close_innermost(out, true)?;
+                after_word = false;
+            }
+        }
+        let too_deep = matches!(&token.kind, TokenKind::StartTag(tag) if !self.dialect.is_void(tag.name))
+            && self.open.len() == MAX_NESTING_DEPTH;
+        // anything but the end of the element means it has more than a line of text in it
+        if !matches!(token.kind, TokenKind::EndTag(_)) && !too_deep && self.write_pending(out)? {
+            after_word = std::mem::replace(&mut self.after_word, false);
+        }
+        if !matches!(token.kind, TokenKind::EndTag(_)) {
+            self.write_held(out)?;
+        }
+        match &token.kind {
+            TokenKind::StartTag(tag) if !self.dialect.is_void(tag.name) => {
+                if too_deep {
+                    errors.push(at.error(ErrorKind::NestingTooDeep, token.raw.len()));
+                    return Ok(false);
+                }
+                let start_tag = match self.lowercase_tag_names {
+                    true => Cow::Owned(lowercase_tag_name(token.raw, tag.name)),
+                    false => Cow::Borrowed(token.raw),
+                };
+                let head = self.selector(&start_tag, tag, after_word);
+                let raw_text = self.dialect.is_raw_text(tag.name);
+                let content = if self.shorthands.one_line && !raw_text {
+                    Content::Pending {
+                        head: head.unwrap_or_else(|| start_tag.to_string()),
+                        start_tag: start_tag.into_owned(),
+                        text: None,
+                    }
+                } else {
+                    out.write(head.as_deref().unwrap_or(&start_tag))?;
+                    Content::Undecided
+                };
+                self.open.push(OpenElement {
+                    name: tag.name.to_string(),
+                    at: at.clone(),
+                    len: token.raw.len(),
+                    content,
+                });
+                if !raw_text && !self.shorthands.one_line {
+                    self.open_scope(out)?;
+                }
+            }
+            TokenKind::EndTag(tag) => {
+                let names = self.open.iter().map(|open| open.name.as_str());
+                let (matching, closes_cleanly) = self.dialect.end_tag_match(names, tag.name);
+                if !closes_cleanly {
+                    let kind = match self.open.last() {
+                        Some(innermost) => ErrorKind::MismatchedEndTag {
+                            expected: innermost.name.clone(),
+                            found: tag.name.to_string(),
+                        },
+                        None => ErrorKind::UnmatchedEndTag(tag.name.to_string()),
+                    };
+                    errors.push(at.error(kind, token.raw.len()));
+                }
+                match matching {
+                    Some(index) => {
+                        // anything still open inside the matched element closes with it
+                        while self.open.len() > index {
+                            let implied =
+                                self.open.len() > index + 1 && self.innermost_end_tag_optional();
+                            self.close_innermost(out, implied)?;
+                        }
+                    }
+                    None => {
+                        self.write_pending(out)?;
+                        self.write_held(out)?;
+                        write_escaped(out, token.raw)?;
+                        self.after_word = true;
+                    }
+                }
+            }
+            TokenKind::Text => self.text(out, token.raw)?,
+            TokenKind::StartTag(tag) | TokenKind::EmptyElementTag(tag) => {
+                write_start_tag(out, token.raw, tag.name, self.lowercase_tag_names)?
+            }
+            _ => out.write(token.raw)?,
+        }
+        Ok(true)
+    }
+
+    fn finish(
+        mut self,
+        out: &mut Output<impl Sink>,
+        errors: &mut Vec<Error>,
+        stopped: bool,
+    ) -> io::Result<()> {
+        if !stopped {
+            let unclosed = self
+                .open
+                .iter()
+                .filter(|unclosed| !self.dialect.end_tag_optional(&unclosed.name));
+            for unclosed in unclosed {
+                let kind = ErrorKind::UnclosedStartTag(unclosed.name.clone());
+                errors.push(unclosed.at.error(kind, unclosed.len));
+            }
+        }
+        while !self.open.is_empty() {
+            let implied = self.innermost_end_tag_optional();
+            self.close_innermost(out, implied)?;
+        }
+        self.write_held(out)?;
+        out.decide(false)
+    }
+}
+
+/// write MXML text with its escape codes replaced by the braces they stand for
+fn write_unescaped(out: &mut Output<impl Sink>, text: &str) -> io::Result<()> {
+    if text.contains('&') {
+        out.write(&replace_bracket_escapes(text))
+    } else {
+        out.write(text)
+    }
+}
+
+/// write text with its braces replaced by escape codes
+fn write_escaped(out: &mut Output<impl Sink>, text: &str) -> io::Result<()> {
+    if text.contains(['{', '}', '&']) {
+        out.write_text(&replace_brackets(text))
+    } else {
+        out.write_text(text)
+    }
+}
+
+/// `name`, lowercased if tag names are being lowercased
+fn tag_name(name: &str, lowercase: bool) -> Cow<'_, str> {
+    if lowercase {
+        Cow::Owned(name.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+fn write_start_tag(
+    out: &mut Output<impl Sink>,
+    raw: &str,
+    name: &str,
+    lowercase: bool,
+) -> io::Result<()> {
+    if lowercase {
+        out.write(&lowercase_tag_name(raw, name))
+    } else {
+        out.write(raw)
+    }
+}
+
+fn write_end_tag(out: &mut Output<impl Sink>, name: &str, lowercase: bool) -> io::Result<()> {
+    out.write("</")?;
+    out.write(&tag_name(name, lowercase))?;
+    out.write(">")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Indent;
+    use crate::Document;
+    use std::collections::{HashMap, HashSet};
+
+    /// snippets where tokens are easy to cut off at the wrong place
+    const SOURCES: [&str; 26] = [
+        "<a> {\n    <b> {text &lbrkt;x&rbrkt;}\n    <br> <img/> { literal }\n} <!-- /a -->\n",
+        "<a title=\"x > y\" data='{'> text { <!-- c > d --> } </a> <b/>",
+        "<script> {if (a < b) { c(); }} <style>p { x: y; </style > <title></title>",
+        "<SCRIPT>x</script> <script>{</script><textarea> }</textarea> é",
+        "<!DOCTYPE note [\n  <!ENTITY x \"y>\">\n  <!-- ] > -->\n]><note> {<![CDATA[{]]>}",
+        "<?xml version=\"1.0\"?>\n<a>\n  <b>\n  </c>\n</a> </d> } {",
+        "<a> { <b> { < c <d e='f>",
+        "a < b <!-- unclosed",
+        "<a><b>text</b><br></a> { &amp;lbrkt; } <!x y",
+        "<p>\n  <i>x</i>\n</p>\n<ul>\n<li>\nitem</li></ul>",
+        "<a>\t{\r\n<b>{}</b>}\r\n<c>",
+        "<",
+        "<a title='x <b> y'>z</a>",
+        "<!DOCTYPE a [ <b> ]> c <!-",
+        "<style>a</styles>b</style> <style> {</styles>} </style>",
+        "<UL><li>a<LI>b <p>c<p>d<ul><li>e</ul></Ul><p>f</b><li>",
+        "<UL> {<Li/> <BR> <Script>{}</SCRIPT>} <!-- /UL --><DIV>x</div>",
+        "<dl>\n<dt>a \n<dd>b<!-- c -->\n</dl><table>\n<tr><td>x\n<td>y </b>\n\t<tbody><tr><td>z\n",
+        "div.card#main {\n  text P.a[title='x &lbrkt;'] \t{ul.x {}} <!-- /P --> e.g. {br.x {}}</b>a.b{",
+        "<div class=\"a b\" id=\"c\">x<p class=\"d\"><i class=x></i>\n<LI Class=\"a\">x</LI><li id=\"e\">",
+        "<ul class=\"a\"><li class=\"b\">x<li class=\"c\"> y\n<li class=\"d\"></ul><Script class=\"e\"></Script>",
+        "<ul><li>a<li> b\n<li>\"c\"<li>d {</li></ul><b>x{y}</b><i class=\"z\">w</i><p> </p><b></b>",
+        "<ul><li><p>a <li>b\n<p>c <div>d</ul><dl><dt><p>x\n<dd><p>y <dt>z</dl><td><p>a\t<td>b",
+        "<li> \"a\" <!-- /li --> <b>: text \n<i>:\n<li> \"a\" div.x {<b> \"x\"y} <b>\"a\nb\" <b>:",
+        "<li>: a {b} c\n<li>: a <b>x</b> c <li>: <!-- c -->\n<p>: }",
+        "<ul><li>a</li> <li>b</li>\n<li>c</li> \n<li> \"d\" \r\n<p>\"Hi\" x<b>y</b></ul>\n<i>z</i>",
+    ];
+
+    /// convert by way of a document tree, which the conversion has to match
+    fn document_conversion(
+        source: &str,
+        direction: Direction,
+        options: &Options,
+    ) -> (String, Vec<Error>) {
+        match direction {
+            Direction::MxmlToXml => {
+                let (mut document, errors, _) = Document::parse_mxml_with_shorthands(
+                    source,
+                    &options.dialect,
+                    &options.shorthands,
+                );
+                if options.remove_annotations {
+                    crate::format::remove_closing_annotations(&mut document.children);
+                }
+                crate::replace_in_nodes(&mut document.children, replace_bracket_escapes);
+                if options.lowercase_tag_names {
+                    document.lowercase_tag_names();
+                }
+                let converted = if options.keep_brace_whitespace {
+                    document.to_xml_keeping_brace_whitespace()
+                } else {
+                    document.to_xml()
+                };
+                (converted, errors)
+            }
+            Direction::XmlToMxml => {
+                let (mut document, errors) =
+                    Document::parse_xml_recovering(source, &options.dialect);
+                crate::replace_in_nodes(&mut document.children, replace_brackets);
+                if options.lowercase_tag_names {
+                    document.lowercase_tag_names();
+                }
+                crate::write_shorthands(&mut document.children, options);
+                (document.to_mxml_styled(&options.style), errors)
+            }
+        }
+    }
+
+    fn assert_matches_document(source: &str, direction: Direction, options: &Options) {
+        let (expected, expected_errors) = document_conversion(source, direction, options);
+        for chunk_size in [1, 2, 3, 5, 8, CHUNK_SIZE] {
+            let mut out = Vec::new();
+            // a slice would hand over all of itself at once
+            let reader = io::BufReader::with_capacity(chunk_size, source.as_bytes());
+            let errors =
+                convert_in_chunks(reader, &mut out, direction, options, chunk_size).unwrap();
+            let context = format!("{source:?} {direction:?} in chunks of {chunk_size}");
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{context}");
+            let locations = |errors: &[Error]| -> Vec<(ErrorKind, usize, usize, usize)> {
+                errors
+                    .iter()
+                    .map(|e| (e.kind().clone(), e.span().start, e.line(), e.column()))
+                    .collect()
+            };
+            assert_eq!(locations(&errors), locations(&expected_errors), "{context}");
+        }
+    }
+
+    fn options() -> Vec<Options> {
+        let styled = Options {
+            dialect: Dialect::html5(),
+            style: MxmlStyle {
+                brace_on_next_line: true,
+                close_on_own_line: true,
+                indent: Indent::Tabs,
+                annotate_min_lines: Some(1),
+                ..MxmlStyle::default()
+            },
+            keep_brace_whitespace: true,
+            lowercase_tag_names: true,
+            remove_annotations: true,
+            shorthands: Shorthands {
+                selectors: true,
+                one_line: true,
+            },
+        };
+        let html = Options {
+            dialect: Dialect::html5(),
+            shorthands: Shorthands {
+                one_line: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        // a dialect leaving out end tags and ignoring case
+        let loose = Options {
+            dialect: Dialect {
+                case_insensitive: true,
+                optional_end_tags: HashMap::from([
+                    ("li".to_string(), HashSet::from(["li".to_string()])),
+                    (
+                        "p".to_string(),
+                        HashSet::from(["p".to_string(), "ul".to_string()]),
+                    ),
+                ]),
+                ..Dialect::xml()
+            },
+            shorthands: Shorthands {
+                selectors: true,
+                ..Shorthands::default()
+            },
+            ..Options::default()
+        };
+        vec![Options::default(), html, styled, loose]
+    }
+
+    #[test]
+    fn matches_document_conversion() {
+        for source in SOURCES {
+            for options in options() {
+                assert_matches_document(source, Direction::MxmlToXml, &options);
+                assert_matches_document(source, Direction::XmlToMxml, &options);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_document_conversion_of_files() {
+        for file in [
+            "resume.html",
+            "resume.mxml",
+            "cdata.xml",
+            "cdata.mxml",
+            "test1.txt",
+        ] {
+            let source = std::fs::read_to_string(format!("tests/files/{file}")).unwrap();
+            for options in options() {
+                assert_matches_document(&source, Direction::MxmlToXml, &options);
+                assert_matches_document(&source, Direction::XmlToMxml, &options);
+            }
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn matches_document_conversion_of_any_source(
+            source in "([<>{}/!?=\"'\\[\\]\\-&;.#:]|<!--|-->|&lbrkt;|[ab \t\n]|é)*",
+            options in proptest::sample::select(options()),
+        ) {
+            assert_matches_document(&source, Direction::MxmlToXml, &options);
+            assert_matches_document(&source, Direction::XmlToMxml, &options);
+        }
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        let mxml = "<a> {".repeat(MAX_NESTING_DEPTH + 1);
+        let xml = "<a>".repeat(MAX_NESTING_DEPTH + 1);
+        for options in options() {
+            assert_matches_document(&mxml, Direction::MxmlToXml, &options);
+            assert_matches_document(&xml, Direction::XmlToMxml, &options);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let source = b"<a> {\xff}";
+        let result = convert_stream(
+            &source[..],
+            Vec::new(),
+            Direction::MxmlToXml,
+            &Options::default(),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // a character cut off between chunks is fine
+        let reader = io::BufReader::with_capacity(1, "<a> {é}".as_bytes());
+        let mut out = Vec::new();
+        let options = Options::default();
+        convert_in_chunks(reader, &mut out, Direction::MxmlToXml, &options, 1).unwrap();
+        assert_eq!(out, "<a>é</a>".as_bytes());
+    }
+}
@@ -0,0 +1,643 @@
+//! Tokenizer shared by the XML and MXML converters
+
+use std::ops::Range;
+
+use crate::dialect::Dialect;
+
+/// The flavour of markup being tokenized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// XML or HTML, where curly braces are ordinary characters
+    Xml,
+    /// MXML, where curly braces open and close scopes
+    Mxml,
+}
+
+/// A tag, as found between a `<` and a `>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag<'a> {
+    /// the tag name, e.g. `div` for `<div class="a">`
+    pub name: &'a str,
+    /// the attributes of the tag, in source order. Always empty for end tags.
+    pub attributes: Vec<Attribute<'a>>,
+}
+
+/// An attribute of a tag, e.g. `class="a"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute<'a> {
+    pub name: &'a str,
+    /// the value without its surrounding quotes, or `None` for attributes
+    /// without a value, like `disabled` in `<input disabled>`
+    pub value: Option<&'a str>,
+}
+
+/// The different kinds of token the tokenizer can produce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind<'a> {
+    /// `<name ...>`
+    StartTag(Tag<'a>),
+    /// `</name>`
+    EndTag(Tag<'a>),
+    /// `<name .../>`
+    EmptyElementTag(Tag<'a>),
+    /// Anything that isn't markup
+    Text,
+    /// The content of a raw text element like `<script>`, which is never markup
+    RawText,
+    /// `<!-- ... -->`
+    Comment,
+    /// `<![CDATA[ ... ]]>`
+    CData,
+    /// `<?target ...?>`
+    ProcessingInstruction,
+    /// `<!DOCTYPE ...>`
+    Doctype,
+    /// `{`, only produced for MXML
+    ScopeOpen,
+    /// `}`, only produced for MXML
+    ScopeClose,
+}
+
+/// A single token, along with where it came from in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    /// the exact source text of the token
+    pub raw: &'a str,
+    /// byte range of the token in the source
+    pub span: Range<usize>,
+}
+
+/// Where the tokenizer is within a raw text element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RawTextState {
+    /// just after the start tag of the raw text element with this name
+    AfterStartTag(String),
+    /// just after the `{` opening the element's scope in MXML
+    InScope,
+}
+
+/// Splits XML or MXML source into tokens. Concatenating the `raw` text of every
+/// token produced gives back the original source.
+pub struct Tokenizer<'a> {
+    source: &'a str,
+    position: usize,
+    syntax: Syntax,
+    dialect: Option<&'a Dialect>,
+    raw_text: Option<RawTextState>,
+    /// whether `source` is the whole input, rather than only the start of it
+    complete: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Create a tokenizer over `source`
+    /// # Params
+    /// source - the text to tokenize
+    /// syntax - whether curly braces should be treated as scopes
+    pub fn new(source: &'a str, syntax: Syntax) -> Self {
+        Tokenizer {
+            source,
+            position: 0,
+            syntax,
+            dialect: None,
+            raw_text: None,
+            complete: true,
+        }
+    }
+
+    /// Treat the content of the dialect's raw text elements as raw text, which is produced
+    /// as a single `RawText` token. In XML the raw text runs up to the element's end tag.
+    /// In MXML it runs up to the `}` balancing the `{` after the start tag, or up to
+    /// the end tag if the start tag isn't followed by a scope.
+    pub fn with_dialect(mut self, dialect: &'a Dialect) -> Self {
+        self.dialect = Some(dialect);
+        self
+    }
+
+    /// Treat `source` as only the start of the input, stopping before any token which
+    /// more input could change, so that input can be tokenized as it arrives. The rest
+    /// is tokenized by a new tokenizer over the untokenized input with more appended,
+    /// resumed from `raw_text_state`.
+    pub(crate) fn incomplete(mut self) -> Self {
+        self.complete = false;
+        self
+    }
+
+    /// Carry on from where another tokenizer left off within a raw text element
+    pub(crate) fn resume(mut self, raw_text_state: Option<RawTextState>) -> Self {
+        self.raw_text = raw_text_state;
+        self
+    }
+
+    /// Where the tokenizer is within a raw text element, for resuming with `resume`
+    pub(crate) fn raw_text_state(&self) -> Option<RawTextState> {
+        self.raw_text.clone()
+    }
+
+    /// The byte offset in the source of the next token
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Produce the next token if it's inside a raw text element
+    /// # Returns
+    /// The kind and length of the token, or `None` to tokenize `rest` normally
+    fn raw_text_token(&mut self, rest: &str) -> Option<(TokenKind<'a>, usize)> {
+        let complete = self.complete;
+        match self.raw_text.take()? {
+            RawTextState::AfterStartTag(name) => {
+                if self.syntax == Syntax::Mxml {
+                    let gap = skip_whitespace(rest, 0);
+                    if rest[gap..].starts_with('{') {
+                        if gap > 0 {
+                            self.raw_text = Some(RawTextState::AfterStartTag(name));
+                            return Some((TokenKind::Text, gap));
+                        }
+                        self.raw_text = Some(RawTextState::InScope);
+                        return Some((TokenKind::ScopeOpen, 1));
+                    }
+                }
+                // the name of an end tag cut off by the end of the input might go on
+                let len = find_end_tag(rest, &name)
+                    .filter(|&i| complete || i + 2 + name.len() < rest.len())
+                    .unwrap_or(rest.len());
+                (len > 0).then_some((TokenKind::RawText, len))
+            }
+            RawTextState::InScope => {
+                let len = find_balancing_brace(rest).unwrap_or(rest.len());
+                (len > 0).then_some((TokenKind::RawText, len))
+            }
+        }
+    }
+
+    /// whether `byte` ends a run of text
+    fn is_text_delimiter(&self, byte: u8) -> bool {
+        byte == b'<' || (self.syntax == Syntax::Mxml && (byte == b'{' || byte == b'}'))
+    }
+
+    /// the length of the text run at the start of `rest`, which always includes
+    /// at least the first character
+    /// # Returns
+    /// the length, or `None` if more input could turn a `<` in the text into markup
+    fn text_len(&self, rest: &str) -> Option<usize> {
+        let bytes = rest.as_bytes();
+        let mut end = 1;
+        while end < bytes.len() {
+            if bytes[end] == b'<' {
+                let found = markup_at(&rest[end..]).is_some();
+                if !self.complete && markup_undecided(&rest[end..], found) {
+                    return None;
+                }
+                if found {
+                    break;
+                }
+            } else if self.is_text_delimiter(bytes[end]) {
+                break;
+            }
+            end += 1;
+        }
+        Some(end)
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = &self.source[self.position..];
+        let first = *rest.as_bytes().first()?;
+
+        let raw_text = self.raw_text.clone();
+        let token = match self.raw_text_token(rest) {
+            Some(token) => Some(token),
+            None => match first {
+                b'{' if self.syntax == Syntax::Mxml => Some((TokenKind::ScopeOpen, 1)),
+                b'}' if self.syntax == Syntax::Mxml => Some((TokenKind::ScopeClose, 1)),
+                b'<' => {
+                    let markup = markup_at(rest);
+                    if !self.complete && markup_undecided(rest, markup.is_some()) {
+                        None
+                    } else {
+                        markup.or_else(|| Some((TokenKind::Text, self.text_len(rest)?)))
+                    }
+                }
+                _ => self.text_len(rest).map(|len| (TokenKind::Text, len)),
+            },
+        };
+        // with more input to come, a token running up to the end of what we have might go on
+        let (kind, len) = match token {
+            Some((kind, len)) if self.complete || len < rest.len() => (kind, len),
+            _ => {
+                self.raw_text = raw_text;
+                return None;
+            }
+        };
+        if let TokenKind::StartTag(tag) = &kind {
+            if self
+                .dialect
+                .is_some_and(|dialect| dialect.is_raw_text(tag.name))
+            {
+                self.raw_text = Some(RawTextState::AfterStartTag(tag.name.to_string()));
+            }
+        }
+
+        let start = self.position;
+        self.position += len;
+        Some(Token {
+            kind,
+            raw: &self.source[start..self.position],
+            span: start..self.position,
+        })
+    }
+}
+
+/// Recognise the markup starting at the `<` at the beginning of `rest`
+/// # Returns
+/// The kind and byte length of the markup, or `None` if the `<` doesn't start any
+fn markup_at(rest: &str) -> Option<(TokenKind<'_>, usize)> {
+    // opaque regions that are never closed extend to the end of the input
+    let until = |start: usize, terminator: &str| {
+        rest[start..]
+            .find(terminator)
+            .map_or(rest.len(), |i| start + i + terminator.len())
+    };
+
+    if rest.starts_with("<!--") {
+        Some((TokenKind::Comment, until(4, "-->")))
+    } else if rest.starts_with("<![CDATA[") {
+        Some((TokenKind::CData, until(9, "]]>")))
+    } else if rest.starts_with("<?") {
+        Some((TokenKind::ProcessingInstruction, until(2, "?>")))
+    } else if rest.starts_with("<!") {
+        let len = scan_declaration(rest).or_else(|| rest.find('>').map(|i| i + 1))?;
+        Some((TokenKind::Doctype, len))
+    } else if let Some(after) = rest.strip_prefix("</") {
+        let len = rest.find('>')? + 1;
+        let tag = Tag {
+            name: tag_name(after),
+            attributes: Vec::new(),
+        };
+        Some((TokenKind::EndTag(tag), len))
+    } else {
+        if !rest[1..].starts_with(is_name_start_char) {
+            return None;
+        }
+        let (tag, len, empty_element) = scan_tag(rest)?;
+        if empty_element {
+            Some((TokenKind::EmptyElementTag(tag), len))
+        } else {
+            Some((TokenKind::StartTag(tag), len))
+        }
+    }
+}
+
+/// Whether more input after `rest`, which starts with a `<`, could change what markup
+/// the `<` starts
+/// # Params
+/// found - whether `markup_at` found any markup at the `<`
+fn markup_undecided(rest: &str, found: bool) -> bool {
+    if rest.starts_with("<!") && !rest.starts_with("<!--") && !rest.starts_with("<![CDATA[") {
+        // without its end, a declaration would fall back to ending at the first `>`
+        return scan_declaration(rest).is_none();
+    }
+    !found
+        && (rest.len() < 2 || rest.starts_with("</") || rest[1..].starts_with(is_name_start_char))
+}
+
+/// Scan the start or empty-element tag at the beginning of `rest`. Attribute
+/// values may be quoted with `"` or `'`, and any `>` or `/` inside the quotes
+/// is part of the value.
+/// # Returns
+/// The tag, its byte length, and whether it is an empty-element tag, or `None`
+/// if the tag is never closed
+fn scan_tag(rest: &str) -> Option<(Tag<'_>, usize, bool)> {
+    let bytes = rest.as_bytes();
+    let name = tag_name(&rest[1..]);
+    let mut attributes = Vec::new();
+    let mut index = 1 + name.len();
+    loop {
+        index = skip_whitespace(rest, index);
+        match bytes.get(index)? {
+            b'>' => return Some((Tag { name, attributes }, index + 1, false)),
+            b'/' if bytes.get(index + 1) == Some(&b'>') => {
+                return Some((Tag { name, attributes }, index + 2, true))
+            }
+            b'/' => {
+                index += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let name_start = index;
+        index = find_or_end(rest, index, |c| {
+            c.is_whitespace() || c == '=' || c == '>' || c == '/'
+        });
+        let attribute_name = &rest[name_start..index];
+
+        let after_name = skip_whitespace(rest, index);
+        let value = if bytes.get(after_name) == Some(&b'=') {
+            index = skip_whitespace(rest, after_name + 1);
+            match *bytes.get(index)? {
+                quote @ (b'"' | b'\'') => {
+                    let value_start = index + 1;
+                    index = value_start + rest[value_start..].find(quote as char)?;
+                    let value = &rest[value_start..index];
+                    index += 1;
+                    Some(value)
+                }
+                _ => {
+                    let value_start = index;
+                    index = find_or_end(rest, index, |c| c.is_whitespace() || c == '>');
+                    Some(&rest[value_start..index])
+                }
+            }
+        } else {
+            None
+        };
+        attributes.push(Attribute {
+            name: attribute_name,
+            value,
+        });
+    }
+}
+
+/// Scan the declaration (e.g. `<!DOCTYPE ...>`) at the beginning of `rest`. Quoted
+/// strings and a bracketed internal subset, along with any comments inside it,
+/// may contain `>` without ending the declaration.
+/// # Returns
+/// The byte length of the declaration, or `None` if it is never closed
+fn scan_declaration(rest: &str) -> Option<usize> {
+    let bytes = rest.as_bytes();
+    let mut in_subset = false;
+    let mut index = 2;
+    loop {
+        match bytes.get(index)? {
+            quote @ (b'"' | b'\'') => {
+                index += 1 + rest[index + 1..].find(*quote as char)?;
+            }
+            b'<' if in_subset && rest[index..].starts_with("<!--") => {
+                index += rest[index..].find("-->")? + 2;
+            }
+            b'[' if !in_subset => in_subset = true,
+            b']' if in_subset => in_subset = false,
+            b'>' if !in_subset => return Some(index + 1),
+            _ => {}
+        }
+        index += 1;
+    }
+}
+
+/// the index of the first character at or after `from` that isn't whitespace
+fn skip_whitespace(s: &str, from: usize) -> usize {
+    find_or_end(s, from, |c| !c.is_whitespace())
+}
+
+/// the index of the first character at or after `from` matching `predicate`,
+/// or the length of `s` if there is none
+fn find_or_end(s: &str, from: usize, predicate: impl Fn(char) -> bool) -> usize {
+    s[from..].find(predicate).map_or(s.len(), |i| from + i)
+}
+
+/// Find the end tag with the given name, ignoring ASCII case like HTML does
+/// # Returns
+/// the index of the `<` of the end tag
+fn find_end_tag(s: &str, name: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut from = 0;
+    while let Some(i) = s[from..].find("</") {
+        let start = from + i;
+        let name_end = start + 2 + name.len();
+        let name_matches = bytes
+            .get(start + 2..name_end)
+            .is_some_and(|found| found.eq_ignore_ascii_case(name.as_bytes()));
+        let name_ends = bytes
+            .get(name_end)
+            .is_none_or(|b| b.is_ascii_whitespace() || *b == b'>' || *b == b'/');
+        if name_matches && name_ends {
+            return Some(start);
+        }
+        from = start + 2;
+    }
+    None
+}
+
+/// Find the `}` which balances a `{` just before the start of `s`
+/// # Returns
+/// the index of the balancing `}`
+fn find_balancing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, byte) in s.bytes().enumerate() {
+        match byte {
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(index),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// whether `c` may start an XML name
+fn is_name_start_char(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':' || !c.is_ascii()
+}
+
+/// the tag name at the start of `s`, which runs up to whitespace, `/` or `>`
+fn tag_name(s: &str) -> &str {
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str, syntax: Syntax) -> Vec<TokenKind<'_>> {
+        Tokenizer::new(source, syntax).map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn tokens_cover_source() {
+        let source = "<!DOCTYPE html><a href=x> { text <b/> <!-- c --> } </a>";
+        let joined: String = Tokenizer::new(source, Syntax::Mxml)
+            .map(|t| t.raw)
+            .collect();
+        assert_eq!(source, joined);
+    }
+
+    #[test]
+    fn tag_kinds() {
+        assert_eq!(
+            kinds("<a x=\"1\"></a><b/>", Syntax::Xml),
+            vec![
+                TokenKind::StartTag(Tag {
+                    name: "a",
+                    attributes: vec![Attribute {
+                        name: "x",
+                        value: Some("1")
+                    }]
+                }),
+                TokenKind::EndTag(Tag {
+                    name: "a",
+                    attributes: vec![]
+                }),
+                TokenKind::EmptyElementTag(Tag {
+                    name: "b",
+                    attributes: vec![]
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn attributes_with_quotes() {
+        let source = "<a title=\"x > y\" data-expr='a<b' href=/path/ hidden>";
+        let tokens: Vec<Token> = Tokenizer::new(source, Syntax::Xml).collect();
+        assert_eq!(tokens.len(), 1);
+        let TokenKind::StartTag(tag) = &tokens[0].kind else {
+            panic!("expected a start tag, got {:?}", tokens[0].kind);
+        };
+        let attributes: Vec<(&str, Option<&str>)> =
+            tag.attributes.iter().map(|a| (a.name, a.value)).collect();
+        assert_eq!(
+            attributes,
+            vec![
+                ("title", Some("x > y")),
+                ("data-expr", Some("a<b")),
+                ("href", Some("/path/")),
+                ("hidden", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn slash_in_quoted_value_is_not_empty_element() {
+        assert!(matches!(
+            kinds("<a href=\"/path/\">", Syntax::Xml)[..],
+            [TokenKind::StartTag(_)]
+        ));
+        assert!(matches!(
+            kinds("<a href=\"/path/\"/>", Syntax::Xml)[..],
+            [TokenKind::EmptyElementTag(_)]
+        ));
+    }
+
+    #[test]
+    fn braces_in_attributes_are_not_scopes() {
+        assert!(matches!(
+            kinds("<a title=\"{x}\"> {", Syntax::Mxml)[..],
+            [
+                TokenKind::StartTag(_),
+                TokenKind::Text,
+                TokenKind::ScopeOpen
+            ]
+        ));
+    }
+
+    #[test]
+    fn unclosed_quote_is_not_a_tag() {
+        assert_eq!(kinds("<a title=\"x>", Syntax::Xml), vec![TokenKind::Text]);
+    }
+
+    #[test]
+    fn braces_only_scopes_in_mxml() {
+        assert_eq!(kinds("{}", Syntax::Xml), vec![TokenKind::Text]);
+        assert_eq!(
+            kinds("{}", Syntax::Mxml),
+            vec![TokenKind::ScopeOpen, TokenKind::ScopeClose]
+        );
+    }
+
+    #[test]
+    fn special_markup() {
+        assert_eq!(
+            kinds(
+                "<?xml version=\"1.0\"?><!DOCTYPE x><!-- <a> --><![CDATA[<b>]]>",
+                Syntax::Xml
+            ),
+            vec![
+                TokenKind::ProcessingInstruction,
+                TokenKind::Doctype,
+                TokenKind::Comment,
+                TokenKind::CData,
+            ]
+        );
+    }
+
+    #[test]
+    fn doctype_internal_subset() {
+        let source = "<!DOCTYPE note [\n  <!ENTITY x \"y>\">\n  <!-- ] > -->\n]><note/>";
+        let tokens: Vec<Token> = Tokenizer::new(source, Syntax::Xml).collect();
+        assert_eq!(tokens[0].kind, TokenKind::Doctype);
+        assert_eq!(tokens[0].raw, &source[..source.find("<note").unwrap()]);
+        assert!(matches!(tokens[1].kind, TokenKind::EmptyElementTag(_)));
+    }
+
+    #[test]
+    fn unclosed_doctype_subset_ends_at_first_angle_bracket() {
+        let source = "<!DOCTYPE a [ > <b>";
+        let tokens: Vec<Token> = Tokenizer::new(source, Syntax::Xml).collect();
+        assert_eq!(tokens[0].raw, "<!DOCTYPE a [ >");
+        assert_eq!(tokens[0].kind, TokenKind::Doctype);
+    }
+
+    #[test]
+    fn raw_text_elements_in_xml() {
+        let dialect = Dialect::html5();
+        let source = "<script>if (a < b) { x(); }</SCRIPT><p>{</p>";
+        let tokens: Vec<Token> = Tokenizer::new(source, Syntax::Xml)
+            .with_dialect(&dialect)
+            .collect();
+        assert_eq!(tokens[1].kind, TokenKind::RawText);
+        assert_eq!(tokens[1].raw, "if (a < b) { x(); }");
+        assert!(matches!(tokens[2].kind, TokenKind::EndTag(_)));
+        assert_eq!(tokens.len(), 6);
+    }
+
+    #[test]
+    fn raw_text_elements_in_mxml() {
+        let dialect = Dialect::svg();
+        let tokenize = |source| -> Vec<TokenKind> {
+            Tokenizer::new(source, Syntax::Mxml)
+                .with_dialect(&dialect)
+                .map(|t| t.kind)
+                .collect()
+        };
+        assert!(matches!(
+            tokenize("<style> {p { a: b; }}")[..],
+            [
+                TokenKind::StartTag(_),
+                TokenKind::Text,
+                TokenKind::ScopeOpen,
+                TokenKind::RawText,
+                TokenKind::ScopeClose
+            ]
+        ));
+        assert!(matches!(
+            tokenize("<style>p { a: b; </style>")[..],
+            [
+                TokenKind::StartTag(_),
+                TokenKind::RawText,
+                TokenKind::EndTag(_)
+            ]
+        ));
+        assert!(matches!(
+            tokenize("<style> {}")[..],
+            [
+                TokenKind::StartTag(_),
+                TokenKind::Text,
+                TokenKind::ScopeOpen,
+                TokenKind::ScopeClose
+            ]
+        ));
+    }
+
+    #[test]
+    fn stray_angle_bracket_is_text() {
+        assert_eq!(kinds("a < b", Syntax::Xml), vec![TokenKind::Text]);
+        assert_eq!(kinds("<", Syntax::Xml), vec![TokenKind::Text]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Reconverting files in a directory whenever they change
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify_debouncer_full::new_debouncer;
+use notify_debouncer_full::notify::event::{EventKind, ModifyKind};
+use notify_debouncer_full::notify::RecursiveMode;
+
+use crate::batch::{self, Batch};
+
+/// how long to wait for a burst of writes (like an editor saving) to settle
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Convert the whole `input` tree into `output`, then keep converting files as they
+/// change until the program is interrupted. Conversion errors are printed, but never stop
+/// the watching.
+pub fn watch(batch: &Batch, input: &Path, output: &Path) -> Result<()> {
+    batch.run(input, output)?;
+
+    // notifications come with absolute paths
+    let input = input
+        .canonicalize()
+        .with_context(|| format!("failed to find `{}`", input.display()))?;
+    let output = output
+        .canonicalize()
+        .with_context(|| format!("failed to find `{}`", output.display()))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer =
+        new_debouncer(DEBOUNCE, None, sender).context("failed to start watching")?;
+    debouncer
+        .watch(&input, RecursiveMode::Recursive)
+        .with_context(|| format!("failed to watch `{}`", input.display()))?;
+    println!("watching {} for changes", input.display());
+
+    for events in receiver {
+        let events = match events {
+            Ok(events) => events,
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("error: {error}");
+                }
+                continue;
+            }
+        };
+        // only writes count, or we'd be woken up again by reading the files ourselves
+        let changed: BTreeSet<_> = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(
+                            ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_)
+                        )
+                )
+            })
+            .flat_map(|event| &event.paths)
+            .collect();
+        for path in changed {
+            // removed files and changes to our own output are nothing to do with us
+            if !path.is_file() || path.starts_with(&output) {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&input) else {
+                continue;
+            };
+            match batch.process(&input, &output, relative) {
+                Ok(outcome) => batch::report(relative, &outcome),
+                Err(error) => eprintln!("error: {error:#}"),
+            }
+        }
+    }
+    Ok(())
+}
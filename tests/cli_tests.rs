@@ -0,0 +1,315 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// run the command line program with `args`, feeding it `stdin`
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mini_markup"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdin_to_stdout() {
+    for args in [&[][..], &["-"], &["-", "-"]] {
+        let output = run(args, "<a> {b}");
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "<a>b</a>");
+    }
+}
+
+#[test]
+fn stdin_to_stdout_with_target() {
+    let output = run(&["-t", "mxml", "-v"], "<a>b</a>");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "<a> {b}");
+}
+
+#[test]
+fn stdin_errors_name_stdin() {
+    let output = run(&[], "<a> {");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("<stdin>:1:5"));
+}
+
+#[test]
+fn direction_from_content() {
+    let output = run(&[], "<a>b</a>");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "<a> {b}");
+}
+
+#[test]
+fn direction_from_extensions() {
+    let dir = std::env::temp_dir().join(format!("mini_markup_cli_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("page.html");
+    let output = dir.join("page.mxml");
+    std::fs::write(&input, "<p>a<br>b</p>").unwrap();
+
+    let result = run(&[input.to_str().unwrap(), output.to_str().unwrap()], "");
+    assert!(result.status.success());
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), "<p> {a<br>b}");
+
+    let result = run(&[output.to_str().unwrap(), output.to_str().unwrap()], "");
+    assert!(!result.status.success());
+    assert!(String::from_utf8(result.stderr)
+        .unwrap()
+        .contains("--target"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn convert_directory() {
+    let dir = std::env::temp_dir().join(format!("mini_markup_batch_{}", std::process::id()));
+    let (input, output) = (dir.join("src"), dir.join("out"));
+    std::fs::create_dir_all(input.join("blog/drafts")).unwrap();
+    std::fs::write(input.join("index.mxml"), "<!DOCTYPE html><p> {a<br>b}").unwrap();
+    std::fs::write(input.join("blog/post.mxml"), "<post> {hi}").unwrap();
+    std::fs::write(input.join("blog/drafts/wip.mxml"), "<post> {").unwrap();
+    std::fs::write(input.join("style.css"), "p { }").unwrap();
+
+    let args = [
+        input.to_str().unwrap(),
+        output.to_str().unwrap(),
+        "--exclude",
+        "**/drafts/**",
+    ];
+    let result = run(&args, "");
+    assert!(result.status.success());
+    let read = |path: &str| std::fs::read_to_string(output.join(path)).unwrap();
+    assert_eq!(read("index.html"), "<!DOCTYPE html><p>a<br>b</p>");
+    assert_eq!(read("blog/post.xml"), "<post>hi</post>");
+    assert_eq!(read("style.css"), "p { }");
+    assert!(!output.join("blog/drafts").exists());
+
+    // without the exclude, the broken draft fails the whole run
+    let result = run(&args[..2], "");
+    assert!(!result.status.success());
+    let summary = String::from_utf8(result.stdout).unwrap();
+    assert!(summary.contains("2 converted, 1 copied, 1 failed"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn convert_directory_carries_on_past_unwritable_files() {
+    let dir = std::env::temp_dir().join(format!("mini_markup_unwritable_{}", std::process::id()));
+    let (input, output) = (dir.join("src"), dir.join("out"));
+    std::fs::create_dir_all(input.join("sub")).unwrap();
+    std::fs::write(input.join("a.mxml"), "<a> {x}").unwrap();
+    std::fs::write(input.join("b.mxml"), "<b> {y}").unwrap();
+    std::fs::write(input.join("c.css"), "c {}").unwrap();
+    std::fs::write(input.join("sub/d.mxml"), "<d> {z}").unwrap();
+    // directories and files in the way of the output
+    std::fs::create_dir_all(output.join("a.xml")).unwrap();
+    std::fs::create_dir_all(output.join("c.css")).unwrap();
+    std::fs::write(output.join("sub"), "").unwrap();
+
+    let result = run(&[input.to_str().unwrap(), output.to_str().unwrap()], "");
+    assert!(!result.status.success());
+    let summary = String::from_utf8(result.stdout).unwrap();
+    assert!(summary.contains("1 converted, 0 copied, 3 failed"));
+    assert_eq!(
+        std::fs::read_to_string(output.join("b.xml")).unwrap(),
+        "<b>y</b>"
+    );
+    let errors = String::from_utf8(result.stderr).unwrap();
+    assert!(errors.contains("failed to write"));
+    assert!(errors.contains("failed to copy"));
+    assert!(errors.contains("failed to create"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn convert_directory_fails_files_with_the_same_output() {
+    let dir = std::env::temp_dir().join(format!("mini_markup_collision_{}", std::process::id()));
+    let (input, output) = (dir.join("in"), dir.join("out"));
+    std::fs::create_dir_all(&input).unwrap();
+    std::fs::write(input.join("p.html"), "<p>html</p>").unwrap();
+    std::fs::write(input.join("p.xml"), "<p>xml</p>").unwrap();
+
+    let result = run(
+        &[
+            "-t",
+            "mxml",
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(!result.status.success());
+    let summary = String::from_utf8(result.stdout).unwrap();
+    assert!(summary.contains("1 converted, 0 copied, 1 failed"));
+    assert_eq!(
+        std::fs::read_to_string(output.join("p.mxml")).unwrap(),
+        "<p> {html}"
+    );
+    let errors = String::from_utf8(result.stderr).unwrap();
+    assert!(errors.contains("`p.xml` would overwrite"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn check_exit_codes() {
+    let output = run(&["check", "-"], "<a> {<b> {x}}");
+    assert!(output.status.success());
+
+    let output = run(&["check", "-", "--html"], "<p> {<img> {} text}");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("warning: `<img>` can't have content"));
+    assert!(stderr.contains("0 error(s), 1 warning(s)"));
+
+    let output = run(&["check", "-", "--html", "-D"], "<p> {<img> {} text}");
+    assert!(!output.status.success());
+
+    let output = run(&["check", "-"], "<a> {");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("1 error(s), 0 warning(s)"));
+}
+
+#[test]
+fn fmt_stdin_and_check() {
+    let output = run(&["fmt"], "<a>{<b>   {x}\n<c> { { y } }}");
+    assert!(output.status.success());
+    let formatted = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        formatted,
+        "<a> {\n    <b> {x}\n    <c> { &lbrkt; y &rbrkt; }\n}\n"
+    );
+
+    let output = run(&["fmt", "--check", "-"], &formatted);
+    assert!(output.status.success());
+    let output = run(&["fmt", "--check", "-"], "<a>{x}");
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "<stdin>\n");
+
+    // files with CRLF line endings keep them
+    let output = run(&["fmt", "--check", "-"], &formatted.replace('\n', "\r\n"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn mxml_style_flags() {
+    let args = [
+        "-t",
+        "mxml",
+        "--no-space-before-brace",
+        "--close-on-own-line",
+        "--tabs",
+    ];
+    let output = run(&args, "<a>\n<b>x\ny</b></a>");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<a>{\n<b>{x\ny\n\t}\n}"
+    );
+}
+
+#[test]
+fn dialects() {
+    // SVG keeps the braces of its CSS as they are
+    let output = run(
+        &["-t", "mxml", "--dialect", "svg"],
+        "<style>a { b }</style>",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<style> {a { b }}"
+    );
+
+    let dir = std::env::temp_dir().join(format!("mini_markup_dialect_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("vue.toml");
+    std::fs::write(
+        &file,
+        "extends = \"html5\"\nraw_text_elements = [\"template\"]\n",
+    )
+    .unwrap();
+    let output = run(
+        &["-t", "mxml", "--dialect-file", file.to_str().unwrap()],
+        "<template><p>{{ x }}</p></template><br>",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<template> {<p>{{ x }}</p>}<br>"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let output = run(&["--dialect", "nope"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("unknown dialect `nope`"));
+}
+
+#[test]
+fn shorthand_selectors() {
+    let output = run(
+        &["-t", "xml", "--shorthand-selectors"],
+        "nav.top#menu {a.link {x}}",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<nav class=\"top\" id=\"menu\"><a class=\"link\">x</a></nav>"
+    );
+
+    // check and fmt read them too, rather than warning about literal braces
+    let output = run(&["check", "--shorthand-selectors", "-"], "p.a {x}");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("0 warning(s)"));
+    let output = run(&["fmt", "--shorthand-selectors"], "<p class=\"a\"> {x}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "p.a {x}\n");
+}
+
+#[test]
+fn shorthand_one_line() {
+    let output = run(
+        &["-t", "xml", "--html", "--shorthand-one-line"],
+        "<ul> {\n<li> \"a\"\n<li>: b\n}",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<ul>\n<li>a</li>\n<li>b</li>\n</ul>"
+    );
+
+    let output = run(&["fmt", "--shorthand-one-line"], "<p> {x}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "<p> \"x\"\n");
+}
+
+#[test]
+fn annotations_only_removed_with_annotate() {
+    let source = "<div>x</div> <!-- /div -->";
+    let mxml = run(&["-t", "mxml"], source).stdout;
+    let output = run(&["-t", "xml"], std::str::from_utf8(&mxml).unwrap());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), source);
+
+    let output = run(
+        &["-t", "xml", "--annotate", "1"],
+        "<div> {\nx\n} <!-- /div -->",
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "<div>\nx\n</div>"
+    );
+}
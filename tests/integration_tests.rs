@@ -1,5 +1,8 @@
 use anyhow::{anyhow, Result};
-use mini_markup::{html_to_mxml, mxml_to_html, mxml_to_xml, xml_to_mxml};
+use mini_markup::{
+    convert_to_fmt, convert_to_writer, html_to_mxml, mxml_to_html, mxml_to_html_str, mxml_to_xml,
+    xml_to_mxml, Dialect, Direction, Options,
+};
 use std::fs;
 
 static TEST_DIR: &str = "./tests/files/";
@@ -103,7 +106,7 @@ test_file_conversion!(
 );
 
 fn html_to_mxml_to_html(source: String) -> Result<String> {
-    mxml_to_html(html_to_mxml(source)?)
+    Ok(mxml_to_html(html_to_mxml(source)?)?)
 }
 
 test_file_conversion!(
@@ -119,3 +122,45 @@ test_file_conversion!(
     "ignore_tagless_curly_braces.txt",
     "ignore_tagless_curly_braces_expected.txt"
 );
+
+test_file_conversion!(cdata_to_mxml, xml_to_mxml, "cdata.xml", "cdata.mxml");
+
+test_file_conversion!(cdata_to_xml, mxml_to_xml, "cdata.mxml", "cdata.xml");
+
+#[test]
+fn conversion_into_sinks() -> Result<()> {
+    let source = fs::read_to_string(format!("{TEST_DIR}resume.mxml"))?;
+    // borrowing the source is enough
+    let expected = mxml_to_html_str(&source)?;
+    let options = Options {
+        dialect: Dialect::html5(),
+        ..Options::default()
+    };
+
+    let mut formatted = String::new();
+    let errors = convert_to_fmt(&source, &mut formatted, Direction::MxmlToXml, &options)?;
+    assert!(errors.is_empty());
+    assert_eq!(formatted, expected);
+
+    let mut written = Vec::new();
+    let errors = convert_to_writer(&source, &mut written, Direction::MxmlToXml, &options)?;
+    assert!(errors.is_empty());
+    assert_eq!(String::from_utf8(written)?, expected);
+    Ok(())
+}
+
+#[test]
+fn sink_errors_show_the_source() -> Result<()> {
+    let mut out = String::new();
+    let errors = convert_to_fmt(
+        "<a>\n  <b> {x}}",
+        &mut out,
+        Direction::MxmlToXml,
+        &Options::default(),
+    )?;
+    // the best-effort output is written all the same
+    assert_eq!(out, "<a>\n  <b>x</b>}");
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].to_string().contains("2 |   <b> {x}}"));
+    Ok(())
+}
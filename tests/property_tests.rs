@@ -0,0 +1,93 @@
+use mini_markup::{
+    convert_recovering, convert_stream, convert_with_options, html_to_mxml, mxml_to_html,
+    mxml_to_xml, xml_to_mxml, Dialect, Dialects, Direction, Document, Options, Shorthands,
+};
+use proptest::prelude::*;
+
+/// strings made mostly of the characters that mean something to the converters,
+/// so that truncated and malformed markup comes up often
+fn markup() -> impl Strategy<Value = String> {
+    let pattern = concat!(
+        "([<>{}/!?=\"'\\[\\]\\-&;.#:]",
+        "|<!--|-->|<!\\[CDATA\\[|]]>|<\\?|\\?>",
+        "|&lbrkt;|&rbrkt;|[ab \t\n]|é)*"
+    );
+    proptest::string::string_regex(pattern).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn conversions_never_panic(source in markup()) {
+        let _ = mxml_to_xml(source.clone());
+        let _ = xml_to_mxml(source.clone());
+        let _ = mxml_to_html(source.clone());
+        let _ = html_to_mxml(source.clone());
+    }
+
+    #[test]
+    fn recovering_conversions_never_panic(source in markup(), html: bool) {
+        for direction in [Direction::MxmlToXml, Direction::XmlToMxml] {
+            if let Err(errors) = convert_recovering(&source, direction, html) {
+                prop_assert!(!errors.is_empty());
+                for error in errors {
+                    // rendering the snippet must be safe too
+                    let _ = error.to_string();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn document_round_trips_source(source in markup()) {
+        // whatever the tree makes of the source, rendering it in the same syntax gives it back
+        if let Ok(document) = Document::parse_xml(&source, &Dialect::html5()) {
+            prop_assert_eq!(document.to_xml(), source.clone());
+        }
+    }
+
+    #[test]
+    fn mxml_document_round_trips_source(source in markup()) {
+        // MXML keeps the whitespace before each `{` in the tree too
+        if let Ok(document) = Document::parse_mxml(&source, &Dialect::xml()) {
+            prop_assert_eq!(document.to_mxml(), source.clone());
+        }
+    }
+
+    #[test]
+    fn xml_round_trips_through_mxml(source in markup()) {
+        if let Ok(mxml) = xml_to_mxml(source.clone()) {
+            prop_assert_eq!(mxml_to_xml(mxml).unwrap(), source.clone());
+        }
+        if let Ok(mxml) = html_to_mxml(source.clone()) {
+            prop_assert_eq!(mxml_to_html(mxml).unwrap(), source);
+        }
+    }
+
+    #[test]
+    fn streaming_matches_converting_text(
+        source in markup(),
+        dialect in prop::sample::select(vec!["xml", "html5", "xhtml", "svg"]),
+        selectors in any::<bool>(),
+        one_line in any::<bool>(),
+    ) {
+        let dialect = Dialects::default().get(dialect).unwrap().clone();
+        let shorthands = Shorthands { selectors, one_line };
+        let options = Options { dialect, shorthands, ..Options::default() };
+        for direction in [Direction::MxmlToXml, Direction::XmlToMxml] {
+            let mut out = Vec::new();
+            let errors = convert_stream(source.as_bytes(), &mut out, direction, &options).unwrap();
+            match convert_with_options(&source, direction, &options) {
+                Ok(converted) => {
+                    prop_assert!(errors.is_empty());
+                    prop_assert_eq!(String::from_utf8(out).unwrap(), converted);
+                }
+                Err(expected) => {
+                    let spans = |errors: &[mini_markup::Error]| -> Vec<_> {
+                        errors.iter().map(|e| (e.kind().clone(), e.span(), e.line(), e.column())).collect()
+                    };
+                    prop_assert_eq!(spans(&errors), spans(&expected));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use mini_markup::{html_to_mxml, mxml_to_html, mxml_to_xml, xml_to_mxml};
+use std::fs;
+
+static TEST_DIR: &str = "./tests/files/";
+
+/// converting to MXML and back should give back exactly the source
+fn assert_round_trips(source: &str, html: bool) {
+    let back = if html {
+        mxml_to_html(html_to_mxml(source.into()).unwrap()).unwrap()
+    } else {
+        mxml_to_xml(xml_to_mxml(source.into()).unwrap()).unwrap()
+    };
+    assert_eq!(back, source);
+}
+
+#[test]
+fn xml_files_round_trip() {
+    for file in [
+        "expected1.txt",
+        "test_readme_expected.txt",
+        "test_ignore_comments.txt",
+        "cdata.xml",
+    ] {
+        let source = fs::read_to_string(format!("{TEST_DIR}{file}")).unwrap();
+        assert_round_trips(&source, false);
+    }
+}
+
+#[test]
+fn html_files_round_trip() {
+    for file in ["resume.html", "curly_braces_in_attr.txt"] {
+        let source = fs::read_to_string(format!("{TEST_DIR}{file}")).unwrap();
+        assert_round_trips(&source, true);
+    }
+}
+
+#[test]
+fn braces_round_trip() {
+    assert_round_trips("<a>{ } }{ {{</a>", false);
+    assert_round_trips("<a title=\"{\" data-x='}'>}</a>", false);
+    assert_round_trips("<!-- { --><a><![CDATA[ } ]]></a>", false);
+}
+
+#[test]
+fn escape_codes_round_trip() {
+    assert_round_trips("<a>&lbrkt; &rbrkt;</a>", false);
+    assert_round_trips("<a>&amp;lbrkt; &amp;amp;rbrkt; &lbrkt</a>", false);
+    assert_round_trips("<a title=\"&lbrkt;\">&amp;</a>", false);
+}
+
+#[test]
+fn html_round_trips() {
+    assert_round_trips("<p>a<br>b</p><script>if (a) { b(); }</script>", true);
+    assert_round_trips("<style>p { }</style><title>&lbrkt; }</title>", true);
+}
+
+#[test]
+fn hand_written_annotations_round_trip() {
+    // comments which look like `--annotate` output are only dropped when asked to be
+    assert_round_trips("<div>x</div> <!-- /div -->", false);
+    assert_round_trips("<ul>\n<li>a</li> <!-- /li -->\n</ul><!-- /ul -->", true);
+}